@@ -0,0 +1,224 @@
+//! Binary format of Merkle hash tree
+//!
+//! All numbers are little-endian:
+//!
+//! magic    4 bytes  "MRKL"
+//! version  1 byte   FORMAT_VERSION
+//! hasher   1 byte   H::ID
+//! shape    1 byte   0 - Bitcoin, 1 - Rfc6962
+//! leaves   8 bytes  number of input blocks
+//! levels   4 bytes  number of tree levels
+//!
+//! Then for each level from zero level to root:
+//!
+//! length   8 bytes                     number of hashes in level
+//! hashes   length * H::OUTPUT_LEN bytes
+//!
+//! Inclusion proof has the same header with magic "MRKP", then:
+//!
+//! index    8 bytes  index of proved leaf
+//! size     8 bytes  number of blocks of the tree
+//! length   4 bytes  number of siblings
+//!
+//! Then for each sibling from zero level:
+//!
+//! side     1 byte               0 - Left, 1 - Right
+//! hash     H::OUTPUT_LEN bytes
+
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use super::{InclusionProof, MerkleError, MerkleHasher, MerkleTree, Side, TreeShape};
+use shape::level_sizes;
+
+const MAGIC: &[u8; 4] = b"MRKL";
+const PROOF_MAGIC: &[u8; 4] = b"MRKP";
+const FORMAT_VERSION: u8 = 1;
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MerkleError> {
+        write_header::<H, W>(writer, MAGIC, self.shape)?;
+        writer.write_all(&(self.num_leaves as u64).to_le_bytes())?;
+        writer.write_all(&(self.tree.len() as u32).to_le_bytes())?;
+
+        for level in &self.tree {
+            writer.write_all(&((level.len() / H::OUTPUT_LEN) as u64).to_le_bytes())?;
+            writer.write_all(level)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<MerkleTree<H>, MerkleError> {
+        let shape = read_header::<H, R>(reader, MAGIC)?;
+        let num_leaves = read_u64(reader)? as usize;
+        let num_level = read_u32(reader)? as usize;
+
+        if num_leaves == 0 || num_leaves > MerkleTree::<H>::max_num_leaves() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        // Length of each level is defined by the length of previous level
+        let sizes = level_sizes(num_leaves, shape);
+
+        if num_level != sizes.len() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        let mut tree: Vec<Vec<u8>> = Vec::with_capacity(num_level);
+
+        for (level, &size) in sizes.iter().enumerate() {
+            if read_u64(reader)? != size as u64 {
+                return Err(MerkleError::InvalidLevel { level });
+            }
+
+            let mut hashes: Vec<u8> = Vec::new();
+            reader
+                .by_ref()
+                .take((size * H::OUTPUT_LEN) as u64)
+                .read_to_end(&mut hashes)?;
+
+            if hashes.len() != size * H::OUTPUT_LEN {
+                return Err(MerkleError::InvalidLevel { level });
+            }
+
+            tree.push(hashes);
+        }
+
+        MerkleTree::from_levels(tree, num_leaves, shape)
+    }
+}
+
+impl<H: MerkleHasher> InclusionProof<H> {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MerkleError> {
+        write_header::<H, W>(writer, PROOF_MAGIC, self.shape)?;
+        writer.write_all(&(self.index as u64).to_le_bytes())?;
+        writer.write_all(&(self.tree_size as u64).to_le_bytes())?;
+        writer.write_all(&(self.path.len() as u32).to_le_bytes())?;
+
+        for &(side, ref hash) in &self.path {
+            writer.write_all(&[side_to_byte(side)])?;
+            writer.write_all(hash)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<InclusionProof<H>, MerkleError> {
+        let shape = read_header::<H, R>(reader, PROOF_MAGIC)?;
+        let index = read_u64(reader)? as usize;
+        let tree_size = read_u64(reader)? as usize;
+        let path_len = read_u32(reader)? as usize;
+
+        if tree_size > MerkleTree::<H>::max_num_leaves() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        // Proof has at most one sibling for each level below root
+        if index >= tree_size || path_len >= level_sizes(tree_size, shape).len() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        let mut path: Vec<(Side, Vec<u8>)> = Vec::with_capacity(path_len);
+
+        for _ in 0..path_len {
+            let mut side = [0u8; 1];
+            reader.read_exact(&mut side)?;
+
+            let mut hash = vec![0u8; H::OUTPUT_LEN];
+            reader.read_exact(&mut hash)?;
+
+            path.push((byte_to_side(side[0])?, hash));
+        }
+
+        Ok(InclusionProof {
+            index,
+            tree_size,
+            shape,
+            path,
+            hasher: PhantomData,
+        })
+    }
+}
+
+/// Magic bytes, version of format, id of hash function and shape
+fn write_header<H: MerkleHasher, W: Write>(
+    writer: &mut W,
+    magic: &[u8; 4],
+    shape: TreeShape,
+) -> Result<(), MerkleError> {
+    writer.write_all(magic)?;
+    writer.write_all(&[FORMAT_VERSION, H::ID, shape_to_byte(shape)])?;
+    Ok(())
+}
+
+/// Check magic bytes, version of format and id of hash function, return shape
+fn read_header<H: MerkleHasher, R: Read>(
+    reader: &mut R,
+    magic: &[u8; 4],
+) -> Result<TreeShape, MerkleError> {
+    let mut found = [0u8; 4];
+    reader.read_exact(&mut found)?;
+
+    if &found != magic {
+        return Err(MerkleError::InvalidHeader);
+    }
+
+    let mut header = [0u8; 3];
+    reader.read_exact(&mut header)?;
+
+    if header[0] != FORMAT_VERSION {
+        return Err(MerkleError::UnsupportedVersion { version: header[0] });
+    }
+
+    if header[1] != H::ID {
+        return Err(MerkleError::HasherMismatch {
+            expected: H::ID,
+            found: header[1],
+        });
+    }
+
+    byte_to_shape(header[2])
+}
+
+fn side_to_byte(side: Side) -> u8 {
+    match side {
+        Side::Left => 0,
+        Side::Right => 1,
+    }
+}
+
+fn byte_to_side(byte: u8) -> Result<Side, MerkleError> {
+    match byte {
+        0 => Ok(Side::Left),
+        1 => Ok(Side::Right),
+        _ => Err(MerkleError::InvalidHeader),
+    }
+}
+
+fn shape_to_byte(shape: TreeShape) -> u8 {
+    match shape {
+        TreeShape::Bitcoin => 0,
+        TreeShape::Rfc6962 => 1,
+    }
+}
+
+fn byte_to_shape(byte: u8) -> Result<TreeShape, MerkleError> {
+    match byte {
+        0 => Ok(TreeShape::Bitcoin),
+        1 => Ok(TreeShape::Rfc6962),
+        _ => Err(MerkleError::InvalidHeader),
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, MerkleError> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, MerkleError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
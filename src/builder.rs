@@ -0,0 +1,110 @@
+//! Streaming construction of Merkle hash tree
+//!
+//! Builder takes input blocks one by one and hashes each of them into zero level at once,
+//! so only hashes are kept in memory and never the blocks themselves. Other levels are
+//! computed by `build` as in `MerkleTree::new`.
+
+use std::marker::PhantomData;
+
+use rayon;
+
+use super::{shared_pool, MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+use shape::hash_leaf;
+
+/// Builder of tree from blocks which are not in memory at the same time
+pub struct MerkleTreeBuilder<H = Sha256Hasher> {
+    /// Zero level of the tree without the copy of last block
+    base: Vec<u8>,
+    num_leaves: usize,
+    shape: TreeShape,
+    hasher: PhantomData<H>,
+}
+
+impl MerkleTreeBuilder {
+    pub fn new() -> MerkleTreeBuilder {
+        MerkleTreeBuilder::with_shape(TreeShape::Bitcoin)
+    }
+}
+
+impl<H: MerkleHasher> Default for MerkleTreeBuilder<H> {
+    fn default() -> MerkleTreeBuilder<H> {
+        MerkleTreeBuilder::with_shape(TreeShape::Bitcoin)
+    }
+}
+
+impl<H: MerkleHasher> MerkleTreeBuilder<H> {
+    /// Builder of tree with hash function H and the given shape
+    pub fn with_shape(shape: TreeShape) -> MerkleTreeBuilder<H> {
+        MerkleTreeBuilder {
+            base: Vec::new(),
+            num_leaves: 0,
+            shape,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Hash the block into zero level
+    pub fn push<B: AsRef<[u8]>>(&mut self, block: B) {
+        let start = self.base.len();
+        self.base.resize(start + H::OUTPUT_LEN, 0u8);
+
+        hash_leaf::<H>(self.shape, block.as_ref(), &mut self.base[start..]);
+        self.num_leaves += 1;
+    }
+
+    /// Hash all blocks of the iterator into zero level. Each block is dropped after hashing
+    pub fn extend<I>(&mut self, blocks: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        for block in blocks {
+            self.push(block);
+        }
+    }
+
+    pub fn get_num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    pub fn get_shape(&self) -> TreeShape {
+        self.shape
+    }
+
+    /// Compute other levels of the tree using `num_cpus` threads
+    pub fn build(self, num_cpus: usize) -> MerkleTree<H> {
+        match self.try_build(num_cpus) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_build(self, num_cpus: usize) -> Result<MerkleTree<H>, MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        self.try_build_in(Some(&pool))
+    }
+
+    pub fn build_in(self, pool: Option<&rayon::ThreadPool>) -> MerkleTree<H> {
+        match self.try_build_in(pool) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_build_in(
+        self,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        if self.num_leaves == 0 {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        Ok(MerkleTree::from_zero_level_in(
+            self.base,
+            self.num_leaves,
+            pool,
+            self.shape,
+        ))
+    }
+}
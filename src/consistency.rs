@@ -0,0 +1,324 @@
+//! Consistency proofs of Merkle hash tree
+//!
+//! Proof shows that tree built from first `old_size` blocks is a prefix of tree built from
+//! `new_size` blocks. Tree covers 2^level blocks by each hash of level: hash `index` of level
+//! `level` covers blocks from index * 2^level to (index + 1) * 2^level.
+//!
+//! For TreeShape::Bitcoin proof contains hashes of the new tree which cover either only blocks
+//! of the old tree (verifier uses them to rebuild both roots) or only appended blocks, ordered
+//! from left to right. Copies of last block are recomputed by verifier.
+//!
+//! For TreeShape::Rfc6962 proof is built and verified exactly as in RFC 6962 (section 2.1.2)
+//! and RFC 9162 (section 2.1.4), so it is compatible with Certificate Transparency logs.
+
+use std::marker::PhantomData;
+use std::slice;
+use std::vec;
+
+use super::{MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+use shape::{checked_level_sizes, hash_pair};
+
+/// Proof that tree of `old_size` blocks is a prefix of tree of `new_size` blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct ConsistencyProof<H = Sha256Hasher> {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub shape: TreeShape,
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub hashes: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    pub fn prove_consistency(&self, old_size: usize) -> ConsistencyProof<H> {
+        match self.try_prove_consistency(old_size) {
+            Ok(proof) => proof,
+            Err(e) => panic!("{} in prove_consistency()", e),
+        }
+    }
+
+    pub fn try_prove_consistency(
+        &self,
+        old_size: usize,
+    ) -> Result<ConsistencyProof<H>, MerkleError> {
+        if old_size == 0 || old_size > self.num_leaves {
+            return Err(MerkleError::SizeOutOfRange { size: old_size });
+        }
+
+        let mut hashes: Vec<Vec<u8>> = Vec::new();
+
+        if old_size < self.num_leaves {
+            match self.shape {
+                TreeShape::Bitcoin => {
+                    self.collect_consistency(self.tree.len() - 1, 0, old_size, &mut hashes)
+                }
+                TreeShape::Rfc6962 => {
+                    self.collect_subproof(old_size, 0, self.num_leaves, true, &mut hashes)
+                }
+            }
+        }
+
+        Ok(ConsistencyProof {
+            old_size,
+            new_size: self.num_leaves,
+            shape: self.shape,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Bitcoin: collect hashes which cover only old blocks or only appended blocks
+    fn collect_consistency(
+        &self,
+        level: usize,
+        index: usize,
+        old_size: usize,
+        hashes: &mut Vec<Vec<u8>>,
+    ) {
+        let start = index << level;
+        let end = (index + 1) << level;
+
+        if end <= old_size || start >= old_size {
+            // Copy of last block is not needed, verifier makes it
+            if start < self.num_leaves {
+                hashes.push(self.get_hash(level, index).to_vec());
+            }
+        } else {
+            self.collect_consistency(level - 1, 2 * index, old_size, hashes);
+            self.collect_consistency(level - 1, 2 * index + 1, old_size, hashes);
+        }
+    }
+
+    /// Rfc6962: SUBPROOF(m, D[start:start + size], complete) of RFC 6962
+    fn collect_subproof(
+        &self,
+        m: usize,
+        start: usize,
+        size: usize,
+        complete: bool,
+        hashes: &mut Vec<Vec<u8>>,
+    ) {
+        if m == size {
+            if !complete {
+                hashes.push(self.subtree_hash(start, size).to_vec());
+            }
+        } else {
+            let k = largest_power_of_two_less_than(size);
+
+            if m <= k {
+                self.collect_subproof(m, start, k, complete, hashes);
+                hashes.push(self.subtree_hash(start + k, size - k).to_vec());
+            } else {
+                self.collect_subproof(m - k, start + k, size - k, false, hashes);
+                hashes.push(self.subtree_hash(start, k).to_vec());
+            }
+        }
+    }
+
+    /// Rfc6962: hash of blocks from start to start + size. Such hash is kept in tree level
+    /// ceil(log2(size)) because start is a multiple of 2^level
+    fn subtree_hash(&self, start: usize, size: usize) -> &[u8] {
+        let level = ceil_log2(size);
+        self.get_hash(level, start >> level)
+    }
+}
+
+/// Check that tree with root `old_root` of `old_size` blocks is a prefix of tree
+/// with root `new_root` of `new_size` blocks, both with the given shape.
+/// Sizes and shape are not taken from the proof (see `verify_inclusion`)
+pub fn verify_consistency<H: MerkleHasher>(
+    old_root: &[u8],
+    old_size: usize,
+    new_root: &[u8],
+    new_size: usize,
+    shape: TreeShape,
+    proof: &ConsistencyProof<H>,
+) -> bool {
+    if old_size == 0 || old_size > new_size || proof.old_size != old_size
+        || proof.new_size != new_size || proof.shape != shape
+        || proof.hashes.iter().any(|hash| hash.len() != H::OUTPUT_LEN)
+    {
+        return false;
+    }
+
+    if old_size == new_size {
+        return proof.hashes.is_empty() && old_root == new_root;
+    }
+
+    match shape {
+        TreeShape::Bitcoin => verify_bitcoin(old_root, old_size, new_root, new_size, proof),
+        TreeShape::Rfc6962 => verify_rfc6962(old_root, old_size, new_root, new_size, proof),
+    }
+}
+
+fn verify_bitcoin<H: MerkleHasher>(
+    old_root: &[u8],
+    old_size: usize,
+    new_root: &[u8],
+    new_size: usize,
+    proof: &ConsistencyProof<H>,
+) -> bool {
+    // Bounds of subtrees are computed as `index << level` up to the next power of two
+    if new_size > usize::MAX >> 1 {
+        return false;
+    }
+
+    let (old_level, new_level) = match (
+        checked_level_sizes(old_size, TreeShape::Bitcoin),
+        checked_level_sizes(new_size, TreeShape::Bitcoin),
+    ) {
+        (Some(old_sizes), Some(new_sizes)) => (old_sizes.len() - 1, new_sizes.len() - 1),
+        _ => return false,
+    };
+
+    let mut hashes = proof.hashes.iter();
+    let mut old_hashes: Vec<Vec<u8>> = Vec::new();
+
+    let new_computed = match new_tree_hash::<H>(
+        new_level,
+        0,
+        old_size,
+        new_size,
+        &mut hashes,
+        &mut old_hashes,
+    ) {
+        Some(hash) => hash,
+        None => return false,
+    };
+
+    if hashes.next().is_some() {
+        return false;
+    }
+
+    let mut old_hashes = old_hashes.into_iter();
+    let old_computed = old_tree_hash::<H>(old_level, 0, old_size, &mut old_hashes);
+
+    old_computed.as_slice() == old_root && new_computed.as_slice() == new_root
+}
+
+/// Bitcoin: hash `index` of level `level` of the new tree. Hashes which cover only old blocks
+/// are saved to `old_hashes` to rebuild the old tree
+fn new_tree_hash<H: MerkleHasher>(
+    level: usize,
+    index: usize,
+    old_size: usize,
+    new_size: usize,
+    hashes: &mut slice::Iter<Vec<u8>>,
+    old_hashes: &mut Vec<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let start = index << level;
+    let end = (index + 1) << level;
+
+    if end <= old_size {
+        let hash = hashes.next()?.clone();
+        old_hashes.push(hash.clone());
+        Some(hash)
+    } else if start >= old_size {
+        hashes.next().cloned()
+    } else {
+        let left =
+            new_tree_hash::<H>(level - 1, 2 * index, old_size, new_size, hashes, old_hashes)?;
+
+        let right = if (2 * index + 1) << (level - 1) < new_size {
+            new_tree_hash::<H>(level - 1, 2 * index + 1, old_size, new_size, hashes, old_hashes)?
+        } else {
+            left.clone()
+        };
+
+        Some(hash_pair::<H>(TreeShape::Bitcoin, &left, &right))
+    }
+}
+
+/// Bitcoin: hash `index` of level `level` of the old tree
+fn old_tree_hash<H: MerkleHasher>(
+    level: usize,
+    index: usize,
+    old_size: usize,
+    old_hashes: &mut vec::IntoIter<Vec<u8>>,
+) -> Vec<u8> {
+    if (index + 1) << level <= old_size {
+        // new_tree_hash has already checked the number of hashes
+        old_hashes.next().unwrap()
+    } else {
+        let left = old_tree_hash::<H>(level - 1, 2 * index, old_size, old_hashes);
+
+        let right = if (2 * index + 1) << (level - 1) < old_size {
+            old_tree_hash::<H>(level - 1, 2 * index + 1, old_size, old_hashes)
+        } else {
+            left.clone()
+        };
+
+        hash_pair::<H>(TreeShape::Bitcoin, &left, &right)
+    }
+}
+
+/// Rfc6962: verification algorithm of RFC 9162, section 2.1.4.2
+fn verify_rfc6962<H: MerkleHasher>(
+    old_root: &[u8],
+    old_size: usize,
+    new_root: &[u8],
+    new_size: usize,
+    proof: &ConsistencyProof<H>,
+) -> bool {
+    let mut path: Vec<&[u8]> = proof.hashes.iter().map(|hash| hash.as_slice()).collect();
+
+    if path.is_empty() {
+        return false;
+    }
+
+    if old_size.is_power_of_two() {
+        path.insert(0, old_root);
+    }
+
+    let mut fn_ = old_size - 1;
+    let mut sn = new_size - 1;
+
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    let mut fr = path[0].to_vec();
+    let mut sr = path[0].to_vec();
+
+    for c in &path[1..] {
+        if sn == 0 {
+            return false;
+        }
+
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = hash_pair::<H>(TreeShape::Rfc6962, c, &fr);
+            sr = hash_pair::<H>(TreeShape::Rfc6962, c, &sr);
+
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            sr = hash_pair::<H>(TreeShape::Rfc6962, &sr, c);
+        }
+
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    sn == 0 && fr.as_slice() == old_root && sr.as_slice() == new_root
+}
+
+fn largest_power_of_two_less_than(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn ceil_log2(n: usize) -> usize {
+    let mut level = 0;
+    while 1 << level < n {
+        level += 1;
+    }
+    level
+}
@@ -0,0 +1,65 @@
+//! Difference of two Merkle hash trees
+//!
+//! Trees are walked from root to zero level and only subtrees with different hashes are
+//! visited, so few differences cost O(log n) hash comparisons each. Hash `index` of level
+//! `level` covers blocks from index * 2^level to (index + 1) * 2^level. Hashes of two trees are
+//! comparable if both trees have the same number of blocks or the hash covers only existing
+//! blocks in both trees, otherwise it depends on the tree size (copy of last block or
+//! moved up hash) and its children are visited.
+
+use std::cmp;
+
+use super::{MerkleHasher, MerkleTree};
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Indices of blocks which differ in two trees or exist only in one of them
+    pub fn diff(&self, other: &MerkleTree<H>) -> Vec<usize> {
+        let mut indices: Vec<usize> = Vec::new();
+
+        if self.shape != other.shape {
+            // Even equal blocks have different hashes
+            indices.extend(0..cmp::max(self.num_leaves, other.num_leaves));
+        } else {
+            let top = cmp::max(self.tree.len(), other.tree.len()) - 1;
+            self.collect_diff(other, top, 0, &mut indices);
+        }
+
+        indices
+    }
+
+    fn collect_diff(
+        &self,
+        other: &MerkleTree<H>,
+        level: usize,
+        index: usize,
+        indices: &mut Vec<usize>,
+    ) {
+        let start = index << level;
+        let end = (index + 1) << level;
+
+        if start >= cmp::max(self.num_leaves, other.num_leaves) {
+            return;
+        }
+
+        let comparable = self.num_leaves == other.num_leaves
+            || (end <= self.num_leaves && end <= other.num_leaves);
+
+        if comparable {
+            if let (Ok(hash), Ok(other_hash)) = (
+                self.try_get_hash(level, index),
+                other.try_get_hash(level, index),
+            ) {
+                if hash == other_hash {
+                    return;
+                }
+            }
+        }
+
+        if level == 0 {
+            indices.push(index);
+        } else {
+            self.collect_diff(other, level - 1, 2 * index, indices);
+            self.collect_diff(other, level - 1, 2 * index + 1, indices);
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Merkle hash tree of a directory
+//!
+//! All regular files under the directory are found recursively (symbolic links are skipped)
+//! and sorted by their relative paths, components are joined with '/' and compared
+//! byte by byte, so the order does not depend on the file system.
+//!
+//! Content hash of each file is the root of the tree of its chunks (as `MerkleTree::from_reader`),
+//! all files are hashed in one pool. The block of file is:
+//!
+//! path     relative path and zero byte, if `DirOptions::include_path`
+//! mode     4 bytes of permission bits (little endian), if `DirOptions::include_mode`
+//! hash     content hash
+//!
+//! Directory without files is one empty block, as empty file is one empty chunk.
+//!
+//! Manifest of directory is a text with the options of hashing:
+//!
+//! merkle-dir-manifest 1
+//! hasher <H::NAME>
+//! chunk-size <bytes>
+//! include-path 0|1
+//! include-mode 0|1
+//! root <hex>
+//!
+//! and then line `<hash> <mode> <path>` for each file in the order of blocks, hash is hex and
+//! mode is octal. Read manifest is checked: paths are sorted and unique, and the files give
+//! the root. It is compared with a later state of the directory hashed with the same options
+//! by `diff_dir`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use rayon;
+
+use super::{from_hex_string, shared_pool, to_hex_string, MerkleError, MerkleHasher, MerkleTree,
+            TreeShape};
+
+const DIR_MANIFEST_HEADER: &str = "merkle-dir-manifest 1";
+
+/// What is hashed into blocks of files besides their contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirOptions {
+    /// Size of chunks of file contents
+    pub chunk_size: usize,
+    /// Renamed file changes the root
+    pub include_path: bool,
+    /// Changed permissions of file change the root
+    pub include_mode: bool,
+}
+
+impl Default for DirOptions {
+    fn default() -> DirOptions {
+        DirOptions {
+            chunk_size: 1024,
+            include_path: false,
+            include_mode: false,
+        }
+    }
+}
+
+/// File of directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Path relative to the directory with components joined by '/'
+    pub path: String,
+    /// Permission bits
+    pub mode: u32,
+    /// Root of the tree of file chunks
+    pub hash: Vec<u8>,
+}
+
+/// Difference of two states of directory, paths are sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Files with other contents or mode
+    pub modified: Vec<String>,
+}
+
+impl DirChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Tree of files of the directory and the files in order of blocks
+    pub fn from_dir<P: AsRef<Path>>(
+        path: P,
+        options: &DirOptions,
+        num_cpus: usize,
+    ) -> Result<(MerkleTree<H>, Vec<DirEntry>), MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::from_dir_in(path, options, Some(&pool))
+    }
+
+    pub fn from_dir_in<P: AsRef<Path>>(
+        path: P,
+        options: &DirOptions,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<(MerkleTree<H>, Vec<DirEntry>), MerkleError> {
+        let mut files: Vec<(String, PathBuf)> = Vec::new();
+        collect_files(path.as_ref(), "", &mut files)?;
+        files.sort();
+
+        let mut entries: Vec<DirEntry> = Vec::with_capacity(files.len());
+        let mut batch: Vec<u8> = Vec::new();
+
+        for (relative, full) in files {
+            let mut file = File::open(&full)?;
+            let mode = file_mode(&file.metadata()?);
+            let (tree, _) = MerkleTree::<H>::from_reader_batch(
+                &mut file,
+                options.chunk_size,
+                pool,
+                &mut batch,
+            )?;
+
+            entries.push(DirEntry {
+                path: relative,
+                mode,
+                hash: tree.get_root().to_vec(),
+            });
+        }
+
+        let tree = MerkleTree::from_dir_entries_in(&entries, options, pool)?;
+
+        Ok((tree, entries))
+    }
+
+    /// Tree of files which are already hashed, entries must be sorted by path
+    pub fn from_dir_entries(
+        entries: &[DirEntry],
+        options: &DirOptions,
+        num_cpus: usize,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::from_dir_entries_in(entries, options, Some(&pool))
+    }
+
+    pub fn from_dir_entries_in(
+        entries: &[DirEntry],
+        options: &DirOptions,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        let mut blocks: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|entry| dir_block(entry, options))
+            .collect();
+
+        if blocks.is_empty() {
+            blocks.push(Vec::new());
+        }
+
+        MerkleTree::try_with_shape_in(&blocks, TreeShape::Bitcoin, pool)
+    }
+}
+
+/// Write manifest of the files hashed with the options, files must be sorted by path
+pub fn write_dir_manifest<H: MerkleHasher, W: Write>(
+    entries: &[DirEntry],
+    options: &DirOptions,
+    writer: &mut W,
+) -> Result<(), MerkleError> {
+    for (index, entry) in entries.iter().enumerate() {
+        // Line breaks would split the line, `lines()` strips trailing '\r' on read
+        if entry.path.contains(['\n', '\r']) {
+            return Err(MerkleError::Io(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} can not be written to manifest", entry.path),
+            )));
+        }
+
+        if index > 0 && entries[index - 1].path >= entry.path {
+            return Err(MerkleError::Io(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} is not sorted or repeated", entry.path),
+            )));
+        }
+    }
+
+    let tree: MerkleTree<H> = MerkleTree::from_dir_entries_in(entries, options, None)?;
+
+    writeln!(writer, "{}", DIR_MANIFEST_HEADER)?;
+    writeln!(writer, "hasher {}", H::NAME)?;
+    writeln!(writer, "chunk-size {}", options.chunk_size)?;
+    writeln!(writer, "include-path {}", options.include_path as u8)?;
+    writeln!(writer, "include-mode {}", options.include_mode as u8)?;
+    writeln!(writer, "root {}", to_hex_string(tree.get_root()))?;
+
+    for entry in entries {
+        writeln!(
+            writer,
+            "{} {:o} {}",
+            to_hex_string(&entry.hash),
+            entry.mode,
+            entry.path
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Read manifest written by `write_dir_manifest` and the options of hashing,
+/// hashes must be of length H::OUTPUT_LEN
+pub fn read_dir_manifest<H: MerkleHasher, R: Read>(
+    reader: &mut R,
+) -> Result<(DirOptions, Vec<DirEntry>), MerkleError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut lines = text.lines();
+
+    if lines.next() != Some(DIR_MANIFEST_HEADER) {
+        return Err(MerkleError::InvalidManifest { line: 1 });
+    }
+
+    if field(lines.next(), "hasher") != Some(H::NAME) {
+        return Err(MerkleError::InvalidManifest { line: 2 });
+    }
+
+    let chunk_size = match field(lines.next(), "chunk-size").and_then(|v| v.parse().ok()) {
+        Some(size) if size > 0 => size,
+        _ => return Err(MerkleError::InvalidManifest { line: 3 }),
+    };
+
+    let include_path = match field(lines.next(), "include-path") {
+        Some("0") => false,
+        Some("1") => true,
+        _ => return Err(MerkleError::InvalidManifest { line: 4 }),
+    };
+
+    let include_mode = match field(lines.next(), "include-mode") {
+        Some("0") => false,
+        Some("1") => true,
+        _ => return Err(MerkleError::InvalidManifest { line: 5 }),
+    };
+
+    let root = match field(lines.next(), "root").and_then(from_hex_string) {
+        Some(ref root) if root.len() == H::OUTPUT_LEN => root.clone(),
+        _ => return Err(MerkleError::InvalidManifest { line: 6 }),
+    };
+
+    let options = DirOptions {
+        chunk_size,
+        include_path,
+        include_mode,
+    };
+
+    let mut entries: Vec<DirEntry> = Vec::new();
+
+    for (index, line) in lines.enumerate() {
+        let mut fields = line.splitn(3, ' ');
+
+        let hash = fields.next().and_then(from_hex_string);
+        let mode = fields
+            .next()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok());
+
+        let line = index + 7;
+
+        match (hash, mode, fields.next()) {
+            (Some(hash), Some(mode), Some(path)) if hash.len() == H::OUTPUT_LEN => {
+                // Paths are sorted and unique, as `MerkleTree::from_dir` gives them
+                if entries.last().is_some_and(|last| last.path.as_str() >= path) {
+                    return Err(MerkleError::InvalidManifest { line });
+                }
+
+                entries.push(DirEntry {
+                    path: path.to_string(),
+                    mode,
+                    hash,
+                })
+            }
+            _ => return Err(MerkleError::InvalidManifest { line }),
+        }
+    }
+
+    let tree: MerkleTree<H> = MerkleTree::from_dir_entries_in(&entries, &options, None)?;
+
+    if tree.get_root() != root.as_slice() {
+        return Err(MerkleError::InvalidManifest { line: 6 });
+    }
+
+    Ok((options, entries))
+}
+
+/// Files added, removed and modified from `old` to `new`, both hashed with `options`.
+/// Change of mode only is a modification if `options.include_mode` is set
+pub fn diff_dir(old: &[DirEntry], new: &[DirEntry], options: &DirOptions) -> DirChanges {
+    let old: BTreeMap<&str, &DirEntry> = old.iter().map(|entry| (&entry.path[..], entry)).collect();
+    let new: BTreeMap<&str, &DirEntry> = new.iter().map(|entry| (&entry.path[..], entry)).collect();
+
+    let mut changes = DirChanges::default();
+
+    for (path, entry) in &new {
+        match old.get(path) {
+            None => changes.added.push(path.to_string()),
+            Some(old_entry)
+                if old_entry.hash != entry.hash
+                    || (options.include_mode && old_entry.mode != entry.mode) =>
+            {
+                changes.modified.push(path.to_string())
+            }
+            Some(_) => {}
+        }
+    }
+
+    for path in old.keys() {
+        if !new.contains_key(path) {
+            changes.removed.push(path.to_string());
+        }
+    }
+
+    changes
+}
+
+/// Value of line `<key> <value>` of text manifest
+pub(crate) fn field<'a>(line: Option<&'a str>, key: &str) -> Option<&'a str> {
+    line.and_then(|line| {
+        let mut parts = line.splitn(2, ' ');
+
+        if parts.next() == Some(key) {
+            parts.next()
+        } else {
+            None
+        }
+    })
+}
+
+fn dir_block(entry: &DirEntry, options: &DirOptions) -> Vec<u8> {
+    let mut block: Vec<u8> = Vec::with_capacity(entry.path.len() + 5 + entry.hash.len());
+
+    if options.include_path {
+        block.extend_from_slice(entry.path.as_bytes());
+        block.push(0);
+    }
+
+    if options.include_mode {
+        block.extend_from_slice(&entry.mode.to_le_bytes());
+    }
+
+    block.extend_from_slice(&entry.hash);
+    block
+}
+
+/// Regular files under `dir` with paths relative to the directory of the walk
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), MerkleError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                return Err(MerkleError::Io(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{:?} is not valid UTF-8", name),
+                )))
+            }
+        };
+
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        if file_type.is_dir() {
+            collect_files(&entry.path(), &relative, files)?;
+        } else if file_type.is_file() {
+            files.push((relative, entry.path()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
@@ -0,0 +1,84 @@
+//! Errors of Merkle hash tree
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum MerkleError {
+    /// There are no input blocks
+    EmptyInput,
+    /// Tree has no level with such index
+    LevelOutOfRange { level: usize },
+    /// Tree level has no hash (or leaf) with such index
+    IndexOutOfRange { index: usize },
+    /// Tree size is zero or greater than number of blocks of the tree
+    SizeOutOfRange { size: usize },
+    /// Error of reading or writing
+    Io(io::Error),
+    /// Serialized tree has wrong magic bytes or inconsistent header
+    InvalidHeader,
+    /// Serialized tree has unknown version of format
+    UnsupportedVersion { version: u8 },
+    /// Serialized tree was built with other hash function
+    HasherMismatch { expected: u8, found: u8 },
+    /// Length of serialized tree level is inconsistent with previous level
+    InvalidLevel { level: usize },
+    /// Input can not be split into chunks of such size
+    InvalidChunkSize { size: usize },
+    /// Precomputed hash has length other than H::OUTPUT_LEN
+    InvalidHashLength { index: usize, length: usize },
+    /// Line of manifest can not be parsed
+    InvalidManifest { line: usize },
+    /// Pool of threads can not be created
+    ThreadPool(String),
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MerkleError::EmptyInput => write!(f, "Length of blocks should be greater ZERO!"),
+            MerkleError::LevelOutOfRange { level } => write!(f, "Invalid level {}!", level),
+            MerkleError::IndexOutOfRange { index } => write!(f, "Invalid index {}!", index),
+            MerkleError::SizeOutOfRange { size } => write!(f, "Invalid tree size {}!", size),
+            MerkleError::Io(ref e) => write!(f, "{}", e),
+            MerkleError::InvalidHeader => write!(f, "Invalid header of serialized tree!"),
+            MerkleError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported version {} of serialized tree!", version)
+            }
+            MerkleError::HasherMismatch { expected, found } => write!(
+                f,
+                "Tree was serialized with hash function {} instead of {}!",
+                found, expected
+            ),
+            MerkleError::InvalidLevel { level } => {
+                write!(f, "Invalid length of serialized level {}!", level)
+            }
+            MerkleError::InvalidChunkSize { size } => write!(f, "Invalid chunk size {}!", size),
+            MerkleError::InvalidHashLength { index, length } => {
+                write!(f, "Invalid length {} of hash {}!", length, index)
+            }
+            MerkleError::InvalidManifest { line } => {
+                write!(f, "Invalid line {} of manifest!", line)
+            }
+            MerkleError::ThreadPool(ref reason) => {
+                write!(f, "Can not create pool of threads: {}!", reason)
+            }
+        }
+    }
+}
+
+impl Error for MerkleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MerkleError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MerkleError {
+    fn from(e: io::Error) -> MerkleError {
+        MerkleError::Io(e)
+    }
+}
@@ -0,0 +1,237 @@
+//! Merkle hash tree of a buffer, a file (or any reader) split into fixed-size chunks
+//!
+//! Each chunk of `chunk_size` bytes is an input block, the last chunk may be shorter.
+//! Empty input is one empty block.
+//!
+//! Reader is read by batches of chunks, chunks of batch are hashed in parallel and dropped,
+//! so only one batch (about `BATCH_BUDGET` bytes, at least one chunk) and the hashes are kept
+//! in memory. Batch buffer grows with read data, so chunk larger than input allocates only input.
+//!
+//! With cargo feature `mmap` the file may be memory-mapped instead of read: chunks are hashed
+//! straight from the mapping and nothing is copied.
+
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap::Mmap;
+use num_cpus;
+use rayon;
+use rayon::prelude::*;
+
+use super::{install, shared_pool, MerkleError, MerkleHasher, MerkleTree, TreeShape};
+use shape::hash_leaf;
+
+/// Number of bytes read at once
+pub(crate) const BATCH_BUDGET: usize = 16 * 1024 * 1024;
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Tree of chunks of the buffer without copying them into separate blocks
+    pub fn from_contiguous(buf: &[u8], chunk_size: usize, num_cpus: usize) -> MerkleTree<H> {
+        match MerkleTree::try_from_contiguous(buf, chunk_size, num_cpus) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_from_contiguous(
+        buf: &[u8],
+        chunk_size: usize,
+        num_cpus: usize,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::try_from_contiguous_in(buf, chunk_size, Some(&pool))
+    }
+
+    pub fn from_contiguous_in(
+        buf: &[u8],
+        chunk_size: usize,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> MerkleTree<H> {
+        match MerkleTree::try_from_contiguous_in(buf, chunk_size, pool) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_from_contiguous_in(
+        buf: &[u8],
+        chunk_size: usize,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        if chunk_size == 0 {
+            return Err(MerkleError::InvalidChunkSize { size: chunk_size });
+        }
+
+        let shape = TreeShape::Bitcoin;
+        let num_leaves = cmp::max(count_chunks(buf.len() as u64, chunk_size), 1) as usize;
+        let mut base = vec![0u8; num_leaves * H::OUTPUT_LEN];
+
+        if buf.is_empty() {
+            hash_leaf::<H>(shape, &[], &mut base);
+        } else {
+            par_chunk_hash::<H>(buf, chunk_size, &mut base, pool, shape);
+        }
+
+        Ok(MerkleTree::from_zero_level_in(base, num_leaves, pool, shape))
+    }
+
+    /// Tree of chunks of the reader and total number of read bytes
+    pub fn from_reader<R: Read>(
+        reader: &mut R,
+        chunk_size: usize,
+        num_cpus: usize,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::from_reader_in(reader, chunk_size, Some(&pool))
+    }
+
+    pub fn from_reader_in<R: Read>(
+        reader: &mut R,
+        chunk_size: usize,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        MerkleTree::from_reader_batch(reader, chunk_size, pool, &mut Vec::new())
+    }
+
+    /// As `from_reader_in`, `batch` is the buffer of read chunks kept between calls
+    pub(crate) fn from_reader_batch<R: Read>(
+        reader: &mut R,
+        chunk_size: usize,
+        pool: Option<&rayon::ThreadPool>,
+        batch: &mut Vec<u8>,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        let shape = TreeShape::Bitcoin;
+        let batch_len = batch_len(chunk_size)?;
+        let mut base: Vec<u8> = Vec::new();
+        let mut total_len: u64 = 0;
+
+        loop {
+            let len = read_batch(reader, batch, batch_len)?;
+
+            if len == 0 {
+                break;
+            }
+
+            total_len += len as u64;
+
+            let start = base.len();
+            let count = count_chunks(len as u64, chunk_size) as usize;
+            base.resize(start + count * H::OUTPUT_LEN, 0u8);
+            par_chunk_hash::<H>(&batch[..len], chunk_size, &mut base[start..], pool, shape);
+
+            if len < batch_len {
+                break;
+            }
+        }
+
+        if total_len == 0 {
+            base.resize(H::OUTPUT_LEN, 0u8);
+            hash_leaf::<H>(shape, &[], &mut base);
+        }
+
+        let num_leaves = base.len() / H::OUTPUT_LEN;
+
+        Ok((
+            MerkleTree::from_zero_level_in(base, num_leaves, pool, shape),
+            total_len,
+        ))
+    }
+
+    /// Tree of chunks of the file using all cpu cores and length of the file
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        chunk_size: usize,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        let mut file = File::open(path)?;
+        MerkleTree::from_reader(&mut file, chunk_size, num_cpus::get())
+    }
+
+    /// Tree of chunks of the memory-mapped file and length of the file.
+    /// The file must not be changed while the tree is built
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap<P: AsRef<Path>>(
+        path: P,
+        chunk_size: usize,
+        num_cpus: usize,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::from_mmap_in(path, chunk_size, Some(&pool))
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn from_mmap_in<P: AsRef<Path>>(
+        path: P,
+        chunk_size: usize,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<(MerkleTree<H>, u64), MerkleError> {
+        if chunk_size == 0 {
+            return Err(MerkleError::InvalidChunkSize { size: chunk_size });
+        }
+
+        let file = File::open(path)?;
+
+        // Empty file can not be mapped
+        if file.metadata()?.len() == 0 {
+            let tree = MerkleTree::try_from_contiguous_in(&[], chunk_size, pool)?;
+            return Ok((tree, 0));
+        }
+
+        let mmap = unsafe { Mmap::map(&file)? };
+        let tree = MerkleTree::try_from_contiguous_in(&mmap, chunk_size, pool)?;
+
+        Ok((tree, mmap.len() as u64))
+    }
+}
+
+/// Parallel hash of `chunk_size` chunks of data into contiguous hashes
+pub(crate) fn par_chunk_hash<H: MerkleHasher>(
+    data: &[u8],
+    chunk_size: usize,
+    hashes: &mut [u8],
+    pool: Option<&rayon::ThreadPool>,
+    shape: TreeShape,
+) {
+    // Chunks longer than data split it the same way, and rayon computes
+    // the number of chunks as `(len + chunk_size - 1) / chunk_size`
+    let chunk_size = cmp::min(chunk_size, cmp::max(data.len(), 1));
+
+    install(pool, || {
+        data.par_chunks(chunk_size)
+            .zip(hashes.par_chunks_mut(H::OUTPUT_LEN))
+            .for_each(|(input, result)| hash_leaf::<H>(shape, input, result));
+    });
+}
+
+/// Number of chunks of `len` bytes, the last one may be shorter
+pub(crate) fn count_chunks(len: u64, chunk_size: usize) -> u64 {
+    let chunk_size = chunk_size as u64;
+    len / chunk_size + if len.is_multiple_of(chunk_size) { 0 } else { 1 }
+}
+
+/// Length of batch of whole chunks, about `BATCH_BUDGET` bytes but at least one chunk
+pub(crate) fn batch_len(chunk_size: usize) -> Result<usize, MerkleError> {
+    if chunk_size == 0 {
+        return Err(MerkleError::InvalidChunkSize { size: chunk_size });
+    }
+
+    cmp::max(1, BATCH_BUDGET / chunk_size)
+        .checked_mul(chunk_size)
+        .ok_or(MerkleError::InvalidChunkSize { size: chunk_size })
+}
+
+/// Read up to `len` bytes into the cleared batch, return number of read bytes
+pub(crate) fn read_batch<R: Read>(
+    reader: &mut R,
+    batch: &mut Vec<u8>,
+    len: usize,
+) -> Result<usize, MerkleError> {
+    batch.clear();
+    reader.by_ref().take(len as u64).read_to_end(batch)?;
+    Ok(batch.len())
+}
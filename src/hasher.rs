@@ -0,0 +1,112 @@
+//! Hash functions for Merkle hash tree
+//!
+//! Tree applies `hash_leaf` to input blocks (zero level) and `hash_node` to concatenation of two
+//! child hashes (other levels). By default they are hash(...) and hash(hash(...)) respectively,
+//! so the implementation of new hash function only needs `OUTPUT_LEN` and `digest`.
+
+use crypto::blake2b::Blake2b;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use crypto::sha2::{Sha256, Sha512};
+use crypto::sha3::Sha3;
+
+pub trait MerkleHasher {
+    /// Size of hash in bytes
+    const OUTPUT_LEN: usize;
+
+    /// Identifier of hash function in serialized trees. Values below 128 are reserved by the crate
+    const ID: u8;
+
+    /// Name of hash function in text formats like manifests
+    const NAME: &'static str;
+
+    /// One pass of hash function, `hashed` has length OUTPUT_LEN
+    fn digest(data: &[u8], hashed: &mut [u8]);
+
+    /// Hash for zero level
+    fn hash_leaf(data: &[u8], hashed: &mut [u8]) {
+        Self::digest(data, hashed);
+    }
+
+    /// Hash for other levels, `data` is concatenation of left and right child hashes
+    fn hash_node(data: &[u8], hashed: &mut [u8]) {
+        let mut first = vec![0u8; Self::OUTPUT_LEN];
+        Self::digest(data, &mut first);
+        Self::digest(&first, hashed);
+    }
+}
+
+/// Sha256 for zero level and sha256(sha256(...)) for other levels. It is the default hasher
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha256Hasher;
+
+/// Sha512 for zero level and sha512(sha512(...)) for other levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha512Hasher;
+
+/// Blake2b with 64 bytes output for zero level and blake2b(blake2b(...)) for other levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Blake2bHasher;
+
+/// Keccak-256 for zero level and keccak256(keccak256(...)) for other levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keccak256Hasher;
+
+/// Sha1 for zero level and sha1(sha1(...)) for other levels. Only for interop with legacy systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha1Hasher;
+
+fn digest_with<D: Digest>(mut digest: D, data: &[u8], hashed: &mut [u8]) {
+    digest.input(data);
+    digest.result(hashed);
+}
+
+impl MerkleHasher for Sha256Hasher {
+    const OUTPUT_LEN: usize = 32;
+    const ID: u8 = 1;
+    const NAME: &'static str = "sha256";
+
+    fn digest(data: &[u8], hashed: &mut [u8]) {
+        digest_with(Sha256::new(), data, hashed);
+    }
+}
+
+impl MerkleHasher for Sha512Hasher {
+    const OUTPUT_LEN: usize = 64;
+    const ID: u8 = 2;
+    const NAME: &'static str = "sha512";
+
+    fn digest(data: &[u8], hashed: &mut [u8]) {
+        digest_with(Sha512::new(), data, hashed);
+    }
+}
+
+impl MerkleHasher for Blake2bHasher {
+    const OUTPUT_LEN: usize = 64;
+    const ID: u8 = 3;
+    const NAME: &'static str = "blake2b";
+
+    fn digest(data: &[u8], hashed: &mut [u8]) {
+        digest_with(Blake2b::new(Self::OUTPUT_LEN), data, hashed);
+    }
+}
+
+impl MerkleHasher for Keccak256Hasher {
+    const OUTPUT_LEN: usize = 32;
+    const ID: u8 = 4;
+    const NAME: &'static str = "keccak256";
+
+    fn digest(data: &[u8], hashed: &mut [u8]) {
+        digest_with(Sha3::keccak256(), data, hashed);
+    }
+}
+
+impl MerkleHasher for Sha1Hasher {
+    const OUTPUT_LEN: usize = 20;
+    const ID: u8 = 5;
+    const NAME: &'static str = "sha1";
+
+    fn digest(data: &[u8], hashed: &mut [u8]) {
+        digest_with(Sha1::new(), data, hashed);
+    }
+}
@@ -5,12 +5,20 @@
 //! Zero level is formed of input byte blocks. If number of block is odd then last block is copy. It is also true for other tree levels (exception is root)
 //! Thus, the length of each tree level (exception is root) is always even
 //!
-//! The used hash function is sha256(...) for zero level and hash_hash = sha256(sha256(...)) for other levels.
-//! The length of sha256 input should be not less than constant SIZE_BLOCK_HASH. It is necessary for crate crypto
-//! The length of hash_hash input should be not less than constant SIZE_INPUT_HASH = 2 * SIZE_BLOCK_HASH. It is necessary for Merkle tree
+//! It is the default TreeShape::Bitcoin. TreeShape::Rfc6962 builds tree compatible with Certificate Transparency logs:
+//! nothing is copied, last block of odd level is moved up unchanged and hashes have 0x00/0x01 prefixes (see module shape)
 //!
-//! Tree level is a vector of contiguous batch of hash bytes which size is SIZE_BLOCK_HASH
-//! Thus, each level contains (number of blocks * SIZE_BLOCK_HASH) hash bytes
+//! The used hash function is set by trait MerkleHasher. By default (Sha256Hasher) it is sha256(...) for zero level
+//! and hash_hash = sha256(sha256(...)) for other levels. Sha512, Blake2b, Keccak-256 and Sha1 are also available.
+//! Input blocks may have any length, including zero
+//!
+//! Tree level is a vector of contiguous batch of hash bytes which size is H::OUTPUT_LEN
+//! Thus, each level contains (number of blocks * H::OUTPUT_LEN) hash bytes
+//!
+//! Functions with parameter `num_cpus` hash in a pool of `num_cpus` threads, which is created
+//! once and shared by all later calls with the same number. Functions with suffix `_in` take
+//! `Option<&rayon::ThreadPool>` instead: the pool of the caller, or the global pool of rayon
+//! if it is None.
 //!
 //! # Example
 //! ```rust,ignore
@@ -22,60 +30,299 @@
 //! let data: Vec<Vec<u8>> = vec![vec![0u8; 32], vec![0u8; 32]]; // or let data = gen_data(2, 32);
 //! let mtree = MerkleTree::new(&data, 1); // second parameter is number of cpu cores
 //! let root: String = to_hex_string(mtree.get_root());
+//!
+//! use merkle_tree::Sha512Hasher;
+//! let mtree_512 = MerkleTree::<Sha512Hasher>::build(&data, 1);
+//!
+//! use merkle_tree::TreeShape;
+//! let mtree_ct = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, TreeShape::Rfc6962);
 //! ```
 //!
 
 extern crate crypto;
+#[cfg(feature = "mmap")]
+extern crate memmap;
+extern crate num_cpus;
 extern crate rand;
 extern crate rayon;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
-use crypto::digest::Digest;
-use crypto::sha2::Sha256;
 use std::vec::Vec;
+use std::marker::PhantomData;
 use rand::Rng;
+use rayon::prelude::*;
 use std::fmt;
-
-const SIZE_INPUT_HASH: usize = 64;
-const SIZE_BLOCK_HASH: usize = 32;
-
-pub struct MerkleTree {
+use std::sync::{Arc, Mutex};
+
+mod binary;
+mod builder;
+mod consistency;
+mod diff;
+mod dir;
+mod error;
+mod file;
+mod hasher;
+mod manifest;
+mod mmr;
+mod multiproof;
+mod proof;
+mod range;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod shape;
+mod sparse;
+mod update;
+
+pub use builder::MerkleTreeBuilder;
+pub use consistency::{verify_consistency, ConsistencyProof};
+pub use dir::{diff_dir, read_dir_manifest, write_dir_manifest, DirChanges, DirEntry,
+              DirOptions};
+pub use error::MerkleError;
+pub use hasher::{Blake2bHasher, Keccak256Hasher, MerkleHasher, Sha1Hasher, Sha256Hasher,
+                 Sha512Hasher};
+pub use manifest::{verify_file, verify_file_in, Manifest, VerifyReport};
+pub use mmr::{verify_mmr, Mmr, MmrProof};
+pub use multiproof::{verify_many, MultiProof};
+pub use proof::{verify_inclusion, InclusionProof, Side};
+pub use range::{verify_range, RangeProof};
+pub use shape::TreeShape;
+pub use sparse::{verify_sparse, SparseMerkleTree, SparseProof};
+
+use shape::level_sizes;
+
+pub struct MerkleTree<H = Sha256Hasher> {
     tree: Vec<Vec<u8>>,
+    num_leaves: usize,
+    shape: TreeShape,
+    hasher: PhantomData<H>,
 }
 
 impl MerkleTree {
-    pub fn new(blocks: &Vec<Vec<u8>>, num_cpus: usize) -> MerkleTree {
-        let num_block = blocks.len();
+    pub fn new<B: AsRef<[u8]> + Sync>(blocks: &[B], num_cpus: usize) -> MerkleTree {
+        MerkleTree::build(blocks, num_cpus)
+    }
 
-        if num_block == 0 {
-            panic!("Length of blocks should be greater ZERO!");
-        } else {
-            use rayon::prelude::*;
-            if blocks.par_iter().any(|block| block.len() < SIZE_BLOCK_HASH) {
-                panic!(
-                    "Length of one or many blocks is less than min size of hash input {} bytes!",
-                    SIZE_BLOCK_HASH
-                );
-            }
+    pub fn try_new<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        num_cpus: usize,
+    ) -> Result<MerkleTree, MerkleError> {
+        MerkleTree::try_build(blocks, num_cpus)
+    }
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Build tree with hash function H
+    pub fn build<B: AsRef<[u8]> + Sync>(blocks: &[B], num_cpus: usize) -> MerkleTree<H> {
+        match MerkleTree::try_build(blocks, num_cpus) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Build tree with hash function H, return error instead of panic on invalid input
+    pub fn try_build<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        num_cpus: usize,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        MerkleTree::try_with_shape(blocks, num_cpus, TreeShape::Bitcoin)
+    }
+
+    /// Build tree with hash function H and the given shape
+    pub fn with_shape<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        num_cpus: usize,
+        shape: TreeShape,
+    ) -> MerkleTree<H> {
+        match MerkleTree::try_with_shape(blocks, num_cpus, shape) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
         }
+    }
 
+    pub fn try_with_shape<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        num_cpus: usize,
+        shape: TreeShape,
+    ) -> Result<MerkleTree<H>, MerkleError> {
         // Pool of thread to speed up calculations of hash function for the current tree level
-        let pool =
-            rayon::ThreadPool::new(rayon::Configuration::new().num_threads(num_cpus)).unwrap();
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::try_with_shape_in(blocks, shape, Some(&pool))
+    }
+
+    /// Build tree with the given shape in the existing pool of threads,
+    /// so many trees may share one pool instead of creating their own
+    pub fn with_shape_in<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        shape: TreeShape,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> MerkleTree<H> {
+        match MerkleTree::try_with_shape_in(blocks, shape, pool) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_with_shape_in<B: AsRef<[u8]> + Sync>(
+        blocks: &[B],
+        shape: TreeShape,
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        let num_block = blocks.len();
+
+        if num_block == 0 {
+            return Err(MerkleError::EmptyInput);
+        }
 
-        let levels = ((num_block + num_block % 2) as f64).log2().ceil() as usize;
+        let sizes = level_sizes(num_block, shape);
 
         // Merkle tree - vector of tree levels, where each level is vector of hash bytes
-        let mut hash_tree: Vec<Vec<u8>> = Vec::with_capacity(levels);
+        let mut hash_tree: Vec<Vec<u8>> = Vec::with_capacity(sizes.len());
 
         // At first, create a zero level applying sha256(...) to each input block of bytes
-        create_hash_zero_level(blocks, &mut hash_tree, &pool, num_cpus);
+        create_hash_zero_level::<H, B>(blocks, &mut hash_tree, pool, shape, sizes[0]);
 
-        for _ in 0..levels {
+        for &size in &sizes[1..] {
             // Then create other levels
-            create_hash_level(&mut hash_tree, &pool, num_cpus);
+            create_hash_level::<H>(&mut hash_tree, pool, shape, size);
+        }
+
+        Ok(MerkleTree {
+            tree: hash_tree,
+            num_leaves: num_block,
+            shape,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Build tree from already computed hashes of zero level (hash_leaf is not applied).
+    /// Each hash must have exactly H::OUTPUT_LEN bytes
+    pub fn from_leaf_hashes<B: AsRef<[u8]>>(hashes: &[B], num_cpus: usize) -> MerkleTree<H> {
+        match MerkleTree::try_from_leaf_hashes(hashes, num_cpus) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_from_leaf_hashes<B: AsRef<[u8]>>(
+        hashes: &[B],
+        num_cpus: usize,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        MerkleTree::try_from_leaf_hashes_in(hashes, Some(&pool))
+    }
+
+    pub fn from_leaf_hashes_in<B: AsRef<[u8]>>(
+        hashes: &[B],
+        pool: Option<&rayon::ThreadPool>,
+    ) -> MerkleTree<H> {
+        match MerkleTree::try_from_leaf_hashes_in(hashes, pool) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_from_leaf_hashes_in<B: AsRef<[u8]>>(
+        hashes: &[B],
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        if hashes.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let mut base: Vec<u8> = Vec::with_capacity(hashes.len() * H::OUTPUT_LEN);
+
+        for (index, hash) in hashes.iter().enumerate() {
+            let hash = hash.as_ref();
+
+            if hash.len() != H::OUTPUT_LEN {
+                return Err(MerkleError::InvalidHashLength {
+                    index,
+                    length: hash.len(),
+                });
+            }
+
+            base.extend_from_slice(hash);
+        }
+
+        Ok(MerkleTree::from_zero_level_in(
+            base,
+            hashes.len(),
+            pool,
+            TreeShape::Bitcoin,
+        ))
+    }
+
+    /// Tree from hashes of zero level without the copy of last block
+    fn from_zero_level_in(
+        mut base: Vec<u8>,
+        num_leaves: usize,
+        pool: Option<&rayon::ThreadPool>,
+        shape: TreeShape,
+    ) -> MerkleTree<H> {
+        let sizes = level_sizes(num_leaves, shape);
+
+        if sizes[0] > num_leaves {
+            base.resize(sizes[0] * H::OUTPUT_LEN, 0u8);
+            copy_last_data(&mut base, sizes[0], H::OUTPUT_LEN);
+        }
+
+        let mut hash_tree: Vec<Vec<u8>> = Vec::with_capacity(sizes.len());
+        hash_tree.push(base);
+
+        for &size in &sizes[1..] {
+            create_hash_level::<H>(&mut hash_tree, pool, shape, size);
         }
 
-        MerkleTree { tree: hash_tree }
+        MerkleTree {
+            tree: hash_tree,
+            num_leaves,
+            shape,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Tree from already computed levels. Number and length of levels are checked
+    fn from_levels(
+        tree: Vec<Vec<u8>>,
+        num_leaves: usize,
+        shape: TreeShape,
+    ) -> Result<MerkleTree<H>, MerkleError> {
+        if num_leaves == 0 {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        if num_leaves > MerkleTree::<H>::max_num_leaves() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        let sizes = level_sizes(num_leaves, shape);
+
+        if tree.len() != sizes.len() {
+            return Err(MerkleError::InvalidHeader);
+        }
+
+        for (level, (hashes, &size)) in tree.iter().zip(sizes.iter()).enumerate() {
+            if hashes.len() != size * H::OUTPUT_LEN {
+                return Err(MerkleError::InvalidLevel { level });
+            }
+        }
+
+        Ok(MerkleTree {
+            tree,
+            num_leaves,
+            shape,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Upper bound of number of leaves of loaded trees, so that size of zero level
+    /// with the copy of last block does not overflow
+    fn max_num_leaves() -> usize {
+        usize::MAX / (2 * H::OUTPUT_LEN)
     }
 
     pub fn get_root(&self) -> &[u8] {
@@ -86,50 +333,94 @@ impl MerkleTree {
         self.tree.len()
     }
 
+    /// Number of input blocks the tree was built from (without the copy of last block)
+    pub fn get_num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    pub fn get_shape(&self) -> TreeShape {
+        self.shape
+    }
+
     pub fn get_level(&self, index: usize) -> &[u8] {
+        match self.try_get_level(index) {
+            Ok(level) => level,
+            Err(e) => panic!("{} in get_level()", e),
+        }
+    }
+
+    pub fn try_get_level(&self, index: usize) -> Result<&[u8], MerkleError> {
         if index > self.tree.len() - 1 {
-            panic!("Invalid index in get_level()!");
+            Err(MerkleError::LevelOutOfRange { level: index })
         } else {
-            &self.tree[index]
+            Ok(&self.tree[index])
         }
     }
 
     pub fn get_hash(&self, level: usize, index: usize) -> &[u8] {
+        match self.try_get_hash(level, index) {
+            Ok(hash) => hash,
+            Err(e) => panic!("{} in get_hash()", e),
+        }
+    }
+
+    pub fn try_get_hash(&self, level: usize, index: usize) -> Result<&[u8], MerkleError> {
         if level > self.tree.len() - 1 {
-            panic!("Invalid level in get_hash()!");
+            Err(MerkleError::LevelOutOfRange { level })
         } else {
-            let num_block = self.tree[level].len() / SIZE_BLOCK_HASH;
+            let num_block = self.tree[level].len() / H::OUTPUT_LEN;
 
             if index > num_block - 1 {
-                panic!("Invalid index in get_hash()!");
+                Err(MerkleError::IndexOutOfRange { index })
             } else {
-                &self.tree[level][index * SIZE_BLOCK_HASH..(index + 1) * SIZE_BLOCK_HASH]
+                Ok(&self.tree[level][index * H::OUTPUT_LEN..(index + 1) * H::OUTPUT_LEN])
             }
         }
     }
 
     pub fn get_parent(&self, level: usize, index: usize) -> &[u8] {
-        if level + 1 > self.tree.len() - 1 {
-            panic!("Invalid level in get_parent()!");
-        } else {
-            let num_block = self.tree[level + 1].len() / SIZE_BLOCK_HASH;
-            let i = ((index as f64) / 2.0).floor() as usize;
+        match self.try_get_parent(level, index) {
+            Ok(parent) => parent,
+            Err(e) => panic!("{} in get_parent()", e),
+        }
+    }
 
-            if i > num_block - 1 {
-                panic!("Invalid index in get_parent()!");
-            } else {
-                &self.tree[level + 1][i * SIZE_BLOCK_HASH..(i + 1) * SIZE_BLOCK_HASH]
-            }
+    pub fn try_get_parent(&self, level: usize, index: usize) -> Result<&[u8], MerkleError> {
+        let parent_level = match level.checked_add(1) {
+            Some(parent_level) if parent_level < self.tree.len() => parent_level,
+            _ => return Err(MerkleError::LevelOutOfRange { level }),
+        };
+        let num_block = self.tree[parent_level].len() / H::OUTPUT_LEN;
+        let i = index / 2;
+
+        if i > num_block - 1 {
+            Err(MerkleError::IndexOutOfRange { index })
+        } else {
+            Ok(&self.tree[parent_level][i * H::OUTPUT_LEN..(i + 1) * H::OUTPUT_LEN])
         }
     }
 
     pub fn get_children(&self, level: usize, index: usize) -> (&[u8], &[u8]) {
+        match self.try_get_children(level, index) {
+            Ok(children) => children,
+            Err(e) => panic!("{} in get_children()", e),
+        }
+    }
+
+    pub fn try_get_children(
+        &self,
+        level: usize,
+        index: usize,
+    ) -> Result<(&[u8], &[u8]), MerkleError> {
         let levels = self.tree.len();
         if level > levels - 1 || level == 0 {
-            panic!("Invalid level in get_children()!");
+            Err(MerkleError::LevelOutOfRange { level })
         } else {
-            let num_block = self.tree[level - 1].len() / SIZE_BLOCK_HASH;
-            let mut i = 2 * index;
+            let num_block = self.tree[level - 1].len() / H::OUTPUT_LEN;
+            let mut i = match index.checked_mul(2) {
+                Some(i) => i,
+                None => return Err(MerkleError::IndexOutOfRange { index }),
+            };
 
             // for blocks which don't have children because
             // it was build by copy last block of previous level with odd number of blocks
@@ -137,41 +428,35 @@ impl MerkleTree {
                 i = num_block - 2;
             }
 
-            if i > num_block - 1 {
-                panic!("Invalid index in get_children()!");
+            // for Rfc6962 block moved up from previous level has only one child
+            if i + 1 > num_block - 1 {
+                Err(MerkleError::IndexOutOfRange { index })
             } else {
-                (
-                    &self.tree[level - 1][i * SIZE_BLOCK_HASH..(i + 1) * SIZE_BLOCK_HASH],
-                    &self.tree[level - 1][(i + 1) * SIZE_BLOCK_HASH..(i + 2) * SIZE_BLOCK_HASH],
-                )
+                Ok((
+                    &self.tree[level - 1][i * H::OUTPUT_LEN..(i + 1) * H::OUTPUT_LEN],
+                    &self.tree[level - 1][(i + 1) * H::OUTPUT_LEN..(i + 2) * H::OUTPUT_LEN],
+                ))
             }
         }
     }
 }
 
 /// Trait for display MerkleTree
-impl fmt::Display for MerkleTree {
+impl<H: MerkleHasher> fmt::Display for MerkleTree<H> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut level: usize = 0;
-        let mut index: usize = 0;
-
-        write!(f, "{}\n", "Tree: ");
+        writeln!(f, "Tree: ")?;
 
-        for hash_level in &self.tree {
-            write!(f, "Level {}: \n", level);
+        for (level, hash_level) in self.tree.iter().enumerate() {
+            writeln!(f, "Level {}: ", level)?;
 
-            for hash in hash_level.chunks(SIZE_BLOCK_HASH) {
-                write!(f, "hash {}: {}\n", index, to_hex_string(hash));
-                index += 1;
+            for (index, hash) in hash_level.chunks(H::OUTPUT_LEN).enumerate() {
+                writeln!(f, "hash {}: {}", index, to_hex_string(hash))?;
             }
 
-            index = 0;
-            level += 1;
-            write!(
+            writeln!(
                 f,
-                "{}\n",
                 "--------------------------------------------------------------------"
-            );
+            )?;
         }
 
         Ok(())
@@ -184,142 +469,156 @@ pub fn to_hex_string(bytes: &[u8]) -> String {
     strs
 }
 
-/// Hash = Sha256 for first stage of hash function
-fn hash_1(data: &[u8]) -> (Vec<u8>, Sha256) {
-    let mut sha = Sha256::new();
+/// Convert string representation (upper or lower case) back to bytes.
+/// Returns None if string has odd length or non-hex characters
+pub fn from_hex_string(strs: &str) -> Option<Vec<u8>> {
+    if !strs.len().is_multiple_of(2) || !strs.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
 
-    let mut hashed: Vec<u8> = Vec::with_capacity(SIZE_BLOCK_HASH);
+    (0..strs.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&strs[i..i + 2], 16).ok())
+        .collect()
+}
 
-    for _ in 0..SIZE_BLOCK_HASH {
-        hashed.push(0u8);
-    }
+/// Convert array slice of u8 to standard base64 (RFC 4648) with padding
+pub fn to_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
-    sha.input(data);
-    sha.result(hashed.as_mut_slice());
-    sha.reset();
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
 
-    (hashed, sha)
-}
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
 
-/// Hash = Sha256(Sha256())
-fn hash_hash(data: &[u8], hashed: &mut [u8]) {
-    let (data, mut sha) = hash_1(data);
-    sha.input(data.as_slice());
-    sha.result(hashed);
-}
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
 
-/// Hash = Sha256 for zero hash level
-fn hash(data: &[u8], hashed: &mut [u8]) {
-    let mut sha = Sha256::new();
-    sha.input(data);
-    sha.result(hashed);
+    encoded
 }
 
 /// Allocate vector with necessary capacity. It creates blueprint of new tree level
 fn create_level(size: usize) -> Vec<u8> {
-    let mut new_level: Vec<u8> = Vec::with_capacity(size);
+    vec![0u8; size]
+}
+
+/// Copy the hash before last over the last one (of previous tree level)
+/// It is used if previous level contains an odd number of byte block
+fn copy_last_data(data: &mut [u8], num_block: usize, size_hash: usize) {
+    data.copy_within(
+        (num_block - 2) * size_hash..(num_block - 1) * size_hash,
+        (num_block - 1) * size_hash,
+    );
+}
+
+/// Pool of `num_threads` threads, created by the first call with this number
+/// and shared by all later ones
+pub(crate) fn shared_pool(num_threads: usize) -> Result<Arc<rayon::ThreadPool>, MerkleError> {
+    static POOLS: Mutex<Vec<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(Vec::new());
 
-    for _ in 0..size {
-        new_level.push(0u8);
+    let mut pools = POOLS.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some((_, pool)) = pools.iter().find(|&&(num, _)| num == num_threads) {
+        return Ok(pool.clone());
     }
 
-    new_level
-}
+    let pool = rayon::ThreadPool::new(rayon::Configuration::new().num_threads(num_threads))
+        .map_err(|e| MerkleError::ThreadPool(e.to_string()))?;
+    let pool = Arc::new(pool);
+    pools.push((num_threads, pool.clone()));
 
-/// Copy data from source to destination by pointers (of previous tree level)
-/// It is used if previous level contains an odd number of byte block
-fn copy_last_data(data: &mut Vec<u8>, num_block: usize) {
-    let src = data.as_ptr();
-    let dst = data.as_mut_ptr();
+    Ok(pool)
+}
 
-    unsafe {
-        for i in (num_block - 2) * SIZE_BLOCK_HASH..(num_block - 1) * SIZE_BLOCK_HASH {
-            *dst.offset((i + SIZE_BLOCK_HASH) as isize) = *src.offset(i as isize);
-        }
+/// Run `op` in the pool of threads, or in the global pool of rayon if it is None
+pub(crate) fn install<R: Send, OP: FnOnce() -> R + Send>(
+    pool: Option<&rayon::ThreadPool>,
+    op: OP,
+) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
     }
 }
 
-/// Parallel hash for tree levels (exception is zero level, it has own function)
-fn par_hash_hash(prev_level: &Vec<u8>, new_level: &mut Vec<u8>, pool: &rayon::ThreadPool) {
-    pool.scope(|scope| {
-        for (input, result) in prev_level
-            .chunks(SIZE_INPUT_HASH)
-            .zip(new_level.chunks_mut(SIZE_BLOCK_HASH))
-        {
-            scope.spawn(move |_| hash_hash(input, result));
-        }
+/// Parallel hash for tree levels (exception is zero level, it has own function).
+/// Work is split by rayon, so small levels do not need a smaller pool
+fn par_hash_hash<H: MerkleHasher>(
+    prev_level: &[u8],
+    new_level: &mut [u8],
+    pool: Option<&rayon::ThreadPool>,
+    shape: TreeShape,
+) {
+    install(pool, || {
+        prev_level
+            .par_chunks(2 * H::OUTPUT_LEN)
+            .zip(new_level.par_chunks_mut(H::OUTPUT_LEN))
+            .for_each(|(input, result)| shape::hash_children::<H>(shape, input, result));
     });
 }
 
-/// Create new level and add it to vector of tree hash levels
-fn create_hash_level(hash_tree: &mut Vec<Vec<u8>>, pool: &rayon::ThreadPool, num_cpus: usize) {
+/// Create new level with `num_block_in_new_level` hashes and add it to vector of tree hash levels
+fn create_hash_level<H: MerkleHasher>(
+    hash_tree: &mut Vec<Vec<u8>>,
+    pool: Option<&rayon::ThreadPool>,
+    shape: TreeShape,
+    num_block_in_new_level: usize,
+) {
     let size_prev_level: usize = hash_tree.last().unwrap().len();
+    let num_block_in_prev_level = size_prev_level / H::OUTPUT_LEN;
 
-    let num_block_in_prev_level = size_prev_level / SIZE_BLOCK_HASH;
-    let addition = (num_block_in_prev_level / 2) % 2;
-    let num_block_in_new_level: usize;
-
-    if num_block_in_prev_level > 2 {
-        num_block_in_new_level = num_block_in_prev_level / 2 + addition;
-    } else {
-        num_block_in_new_level = 1;
-    }
-
-    let mut new_level = create_level(num_block_in_new_level * SIZE_BLOCK_HASH);
+    let mut new_level = create_level(num_block_in_new_level * H::OUTPUT_LEN);
 
-    {
-        let prev_level = hash_tree.last().unwrap();
-        let num_thread: usize;
+    par_hash_hash::<H>(hash_tree.last().unwrap(), &mut new_level, pool, shape);
 
-        if pool.current_num_threads() > num_block_in_new_level {
-            let pool = rayon::ThreadPool::new(
-                rayon::Configuration::new().num_threads(num_block_in_new_level),
-            ).unwrap();
-            par_hash_hash(&prev_level, &mut new_level, &pool);
-        } else {
-            par_hash_hash(&prev_level, &mut new_level, &pool);
-        }
-    }
-
-    if addition == 1 && num_block_in_prev_level > 2 {
-        copy_last_data(&mut new_level, num_block_in_new_level);
+    // Only Bitcoin shape reserves place for the copy of last block
+    if num_block_in_new_level > num_block_in_prev_level.div_ceil(2) {
+        copy_last_data(&mut new_level, num_block_in_new_level, H::OUTPUT_LEN);
     }
 
     hash_tree.push(new_level);
 }
 
 /// Parallel hash for zero level
-fn par_zero_hash(blocks: &Vec<Vec<u8>>, base: &mut Vec<u8>, pool: &rayon::ThreadPool) {
-    pool.scope(|scope| {
-        for (input, result) in blocks.iter().zip(base.chunks_mut(SIZE_BLOCK_HASH)) {
-            scope.spawn(move |_| hash(input, result));
-        }
+fn par_zero_hash<H: MerkleHasher, B: AsRef<[u8]> + Sync>(
+    blocks: &[B],
+    base: &mut [u8],
+    pool: Option<&rayon::ThreadPool>,
+    shape: TreeShape,
+) {
+    install(pool, || {
+        blocks
+            .par_iter()
+            .zip(base.par_chunks_mut(H::OUTPUT_LEN))
+            .for_each(|(input, result)| shape::hash_leaf::<H>(shape, input.as_ref(), result));
     });
 }
 
-/// Create zero level and add it to vector of tree hash levels
-fn create_hash_zero_level(
-    blocks: &Vec<Vec<u8>>,
+/// Create zero level with `num_block` hashes and add it to vector of tree hash levels
+fn create_hash_zero_level<H: MerkleHasher, B: AsRef<[u8]> + Sync>(
+    blocks: &[B],
     hash_tree: &mut Vec<Vec<u8>>,
-    pool: &rayon::ThreadPool,
-    num_cpus: usize,
+    pool: Option<&rayon::ThreadPool>,
+    shape: TreeShape,
+    num_block: usize,
 ) {
-    let size = blocks.len();
-    let num_block = size + size % 2;
+    let mut base: Vec<u8> = create_level(num_block * H::OUTPUT_LEN);
 
-    let mut base: Vec<u8> = create_level(num_block * SIZE_BLOCK_HASH);
-
-    if pool.current_num_threads() > num_block {
-        let pool =
-            rayon::ThreadPool::new(rayon::Configuration::new().num_threads(num_block)).unwrap();
-
-        par_zero_hash(&blocks, &mut base, &pool);
-    } else {
-        par_zero_hash(&blocks, &mut base, &pool);
-    }
+    par_zero_hash::<H, B>(blocks, &mut base, pool, shape);
 
-    if size % 2 == 1 {
-        copy_last_data(&mut base, num_block);
+    if num_block > blocks.len() {
+        copy_last_data(&mut base, num_block, H::OUTPUT_LEN);
     }
 
     hash_tree.push(base);
@@ -1,79 +1,463 @@
+//! Command-line tool to compute Merkle hash tree of a file
+//!
+//! File is split into chunks of `--chunk-size` bytes (the last chunk may be shorter),
+//! each chunk is an input block of the tree. File `-` is the standard input.
+//!
+//! Command `dir` hashes all files of a directory, with `--manifest` it prints files changed
+//! since the manifest was written by `--out` with the same `--chunk-size`, `--with-path`
+//! and `--with-mode`.
+//!
+//! Command `manifest` writes the manifest of the file (chunk size, length, hashes of chunks
+//! and root) to `--out`.
+//!
+//! Command `verify` takes the number of chunks of the tree from `--size` or from the manifest
+//! `--manifest`, never from the proof: a forged proof may present an inner node as the leaf
+//! of a smaller tree. With `--manifest` the root is the root of the manifest.
+//!
+//! Command `verify` exits with code 1 if proof does not match and command `dir` if files
+//! are changed, other errors exit with code 2.
+
 extern crate merkle_tree;
 extern crate num_cpus;
-extern crate time;
 
-use merkle_tree::MerkleTree;
-use merkle_tree::to_hex_string;
-use merkle_tree::gen_data;
-use time::PreciseTime;
-use std::string::ToString;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::process;
+
+use merkle_tree::{diff_dir, from_hex_string, read_dir_manifest, to_base64, to_hex_string,
+                  verify_inclusion, write_dir_manifest, DirOptions, InclusionProof, Manifest,
+                  MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+
+const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+const USAGE: &str = "Usage:
+    merkle root <file> [--chunk-size N] [--jobs N] [--format hex|base64|json]
+    merkle tree <file> [--chunk-size N] [--jobs N] [--format hex|base64|json]
+    merkle prove <file> --index I --out <proof> [--chunk-size N] [--jobs N]
+    merkle manifest <file> --out <manifest> [--chunk-size N] [--jobs N]
+    merkle verify --root HEX --leaf <chunk> --proof <proof> (--size N | --manifest <manifest>)
+    merkle dir <path> [--with-path] [--with-mode] [--out <manifest>] [--manifest <manifest>]
+               [--chunk-size N] [--jobs N] [--format hex|base64|json]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Hex,
+    Base64,
+    Json,
+}
+
+struct Options {
+    file: Option<String>,
+    chunk_size: usize,
+    jobs: usize,
+    format: Format,
+    index: Option<usize>,
+    size: Option<usize>,
+    out: Option<String>,
+    root: Option<String>,
+    leaf: Option<String>,
+    proof: Option<String>,
+    manifest: Option<String>,
+    with_path: bool,
+    with_mode: bool,
+}
 
 fn main() {
-    // Test 1: one core vs few cores with big data size and small block size
-    println!(
-        "{}",
-        "Test 1: one core vs few cores with big data size and small block size".to_string()
-    );
-
-    let mut num_block: usize = 8 * 2;
-    let mut size_block: usize = 32;
-
-    let data_1 = gen_data(num_block, size_block);
-
-    let start = PreciseTime::now();
-    let mtree1 = MerkleTree::new(&data_1, 1);
-    let end = PreciseTime::now();
-
-    println!("{}", mtree1);
-
-    // println!(
-    //     "Time of parrallel version with number of block {} and block size {}: {}",
-    //     num_block,
-    //     size_block,
-    //     start.to(end)
-    // );
-
-    // let start = PreciseTime::now();
-    // let mtree2 = MerkleTree::new(&data_1, 1);
-    // let end = PreciseTime::now();
-
-    // println!(
-    //     "Time of sequence version with number of block {} and block size {}: {}",
-    //     num_block,
-    //     size_block,
-    //     start.to(end)
-    // );
-
-    // // Test 2: one core vs few cores with small size data and big block size
-    // println!(
-    //     "{}",
-    //     "Test 2: one core vs few cores with small size data and big block size".to_string()
-    // );
-
-    // num_block = 8 * 64;
-    // size_block = 8192;
-
-    // let data_1 = gen_data(num_block, size_block);
-
-    // let start = PreciseTime::now();
-    // let mtree1 = MerkleTree::new(&data_1, num_cpus::get());
-    // let end = PreciseTime::now();
-
-    // println!(
-    //     "Time of parrallel version with number of block {} and block size {}: {}",
-    //     num_block,
-    //     size_block,
-    //     start.to(end)
-    // );
-
-    // let start = PreciseTime::now();
-    // let mtree2 = MerkleTree::new(&data_1, 1);
-    // let end = PreciseTime::now();
-
-    // println!(
-    //     "Time of sequence version with number of block {} and block size {}: {}",
-    //     num_block,
-    //     size_block,
-    //     start.to(end)
-    // );
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("merkle: {}", message);
+            process::exit(2);
+        }
+    }
+}
+
+/// Run command, return false if verification failed or files are changed
+fn run(args: &[String]) -> Result<bool, String> {
+    let command = match args.first() {
+        Some(command) => command.as_str(),
+        None => return Err(USAGE.to_string()),
+    };
+
+    let options = parse_options(&args[1..])?;
+
+    match command {
+        "root" => {
+            let (tree, total_len) = build_tree(&options)?;
+            print_root(&tree, total_len, options.format);
+            Ok(true)
+        }
+        "tree" => {
+            let (tree, total_len) = build_tree(&options)?;
+            print_tree(&tree, total_len, options.format);
+            Ok(true)
+        }
+        "prove" => {
+            let index = required(options.index, "--index")?;
+            let out = required(options.out.as_ref(), "--out")?;
+
+            let (tree, _) = build_tree(&options)?;
+            let proof = tree.try_prove(index).map_err(|e| e.to_string())?;
+
+            let mut file = File::create(out).map_err(|e| format!("{}: {}", out, e))?;
+            proof
+                .write_to(&mut file)
+                .map_err(|e| format!("{}: {}", out, e))?;
+
+            println!("{}", to_hex_string(tree.get_root()));
+            Ok(true)
+        }
+        "manifest" => {
+            let out = required(options.out.as_ref(), "--out")?;
+            let manifest = build_manifest(&options)?;
+
+            let mut file = File::create(out).map_err(|e| format!("{}: {}", out, e))?;
+            manifest
+                .write_to(&mut file)
+                .map_err(|e| format!("{}: {}", out, e))?;
+
+            println!("{}", to_hex_string(&manifest.root));
+            Ok(true)
+        }
+        "verify" => {
+            let leaf = required(options.leaf.as_ref(), "--leaf")?;
+            let proof = required(options.proof.as_ref(), "--proof")?;
+
+            let root = match options.root {
+                Some(ref root) => Some(from_hex_string(root).ok_or("--root must be a hex string")?),
+                None => None,
+            };
+
+            // Number of chunks comes from the user or from the manifest, never from the proof
+            let (root, size, shape) = match (options.size, options.manifest.as_ref()) {
+                (Some(size), None) => (required(root, "--root")?, size, TreeShape::Bitcoin),
+                (None, Some(path)) => {
+                    let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+                    let manifest: Manifest =
+                        Manifest::read_from(&mut file).map_err(|e| format!("{}: {}", path, e))?;
+
+                    if root.is_some() && root.as_ref() != Some(&manifest.root) {
+                        return Err(format!("{}: --root is not the root of manifest", path));
+                    }
+
+                    let size = manifest.get_num_chunks();
+                    (manifest.root, size, manifest.shape)
+                }
+                (Some(_), Some(_)) => {
+                    return Err("--size and --manifest can not be used together".to_string())
+                }
+                (None, None) => {
+                    return Err(format!(
+                        "--size N or --manifest <manifest> is required: the number of chunks \
+                         is not taken from the proof, a forged proof may present an inner node \
+                         as the leaf of a smaller tree\n{}",
+                        USAGE
+                    ))
+                }
+            };
+
+            let leaf_data = fs::read(leaf).map_err(|e| format!("{}: {}", leaf, e))?;
+
+            let mut file = File::open(proof).map_err(|e| format!("{}: {}", proof, e))?;
+            let proof: InclusionProof = InclusionProof::read_from(&mut file)
+                .map_err(|e| format!("{}: {}", proof, e))?;
+
+            // Shape in the proof is not trusted either
+            if verify_inclusion(&root, size, shape, &leaf_data, &proof) {
+                println!("OK");
+                Ok(true)
+            } else {
+                println!("FAILED");
+                Ok(false)
+            }
+        }
+        "dir" => run_dir(&options),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut file: Option<String> = None;
+    let mut chunk_size = DEFAULT_CHUNK_SIZE;
+    let mut jobs = num_cpus::get();
+    let mut format = Format::Hex;
+    let mut index: Option<usize> = None;
+    let mut size: Option<usize> = None;
+    let mut out: Option<String> = None;
+    let mut root: Option<String> = None;
+    let mut leaf: Option<String> = None;
+    let mut proof: Option<String> = None;
+    let mut manifest: Option<String> = None;
+    let mut with_path = false;
+    let mut with_mode = false;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--chunk-size" => chunk_size = parse_positive(arg, args.next())?,
+            "--jobs" => jobs = parse_positive(arg, args.next())?,
+            "--index" => index = Some(parse_number(arg, args.next())?),
+            "--size" => size = Some(parse_positive(arg, args.next())?),
+            "--out" => out = Some(parse_value(arg, args.next())?),
+            "--root" => root = Some(parse_value(arg, args.next())?),
+            "--leaf" => leaf = Some(parse_value(arg, args.next())?),
+            "--proof" => proof = Some(parse_value(arg, args.next())?),
+            "--manifest" => manifest = Some(parse_value(arg, args.next())?),
+            "--with-path" => with_path = true,
+            "--with-mode" => with_mode = true,
+            "--format" => {
+                format = match args.next().map(|value| value.as_str()) {
+                    Some("hex") => Format::Hex,
+                    Some("base64") => Format::Base64,
+                    Some("json") => Format::Json,
+                    _ => return Err("--format must be hex, base64 or json".to_string()),
+                }
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if file.is_none() => file = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    Ok(Options {
+        file,
+        chunk_size,
+        jobs,
+        format,
+        index,
+        size,
+        out,
+        root,
+        leaf,
+        proof,
+        manifest,
+        with_path,
+        with_mode,
+    })
+}
+
+fn run_dir(options: &Options) -> Result<bool, String> {
+    let path = match options.file {
+        Some(ref path) => path,
+        None => return Err(USAGE.to_string()),
+    };
+
+    let dir_options = DirOptions {
+        chunk_size: options.chunk_size,
+        include_path: options.with_path,
+        include_mode: options.with_mode,
+    };
+
+    // Hashes of files are comparable only if they are computed with the same options
+    let old = match options.manifest {
+        Some(ref manifest) => {
+            let mut file = File::open(manifest).map_err(|e| format!("{}: {}", manifest, e))?;
+            let (old_options, old) = read_dir_manifest::<Sha256Hasher, _>(&mut file)
+                .map_err(|e| format!("{}: {}", manifest, e))?;
+
+            if old_options != dir_options {
+                return Err(format!(
+                    "{}: written with --chunk-size {}{}{}",
+                    manifest,
+                    old_options.chunk_size,
+                    if old_options.include_path { " --with-path" } else { "" },
+                    if old_options.include_mode { " --with-mode" } else { "" }
+                ));
+            }
+
+            Some(old)
+        }
+        None => None,
+    };
+
+    let (tree, entries) = MerkleTree::<Sha256Hasher>::from_dir(path, &dir_options, options.jobs)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    if let Some(ref out) = options.out {
+        let mut file = File::create(out).map_err(|e| format!("{}: {}", out, e))?;
+        write_dir_manifest::<Sha256Hasher, _>(&entries, &dir_options, &mut file)
+            .map_err(|e| format!("{}: {}", out, e))?;
+    }
+
+    let root = encode(tree.get_root(), options.format);
+
+    if options.format == Format::Json {
+        println!("{{\"root\":{},\"files\":{}}}", json_string(&root), entries.len());
+    } else {
+        println!("{}", root);
+    }
+
+    match old {
+        Some(old) => {
+            let changes = diff_dir(&old, &entries, &dir_options);
+
+            for path in &changes.added {
+                println!("added: {}", path);
+            }
+
+            for path in &changes.removed {
+                println!("removed: {}", path);
+            }
+
+            for path in &changes.modified {
+                println!("modified: {}", path);
+            }
+
+            Ok(changes.is_empty())
+        }
+        None => Ok(true),
+    }
+}
+
+fn parse_value(option: &str, value: Option<&String>) -> Result<String, String> {
+    match value {
+        Some(value) => Ok(value.clone()),
+        None => Err(format!("{} requires a value", option)),
+    }
+}
+
+fn parse_number(option: &str, value: Option<&String>) -> Result<usize, String> {
+    match value.and_then(|value| value.parse::<usize>().ok()) {
+        Some(number) => Ok(number),
+        None => Err(format!("{} must be a number", option)),
+    }
+}
+
+fn parse_positive(option: &str, value: Option<&String>) -> Result<usize, String> {
+    match parse_number(option, value) {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("{} must be a positive number", option)),
+    }
+}
+
+fn required<T>(value: Option<T>, option: &str) -> Result<T, String> {
+    match value {
+        Some(value) => Ok(value),
+        None => Err(format!("{} is required\n{}", option, USAGE)),
+    }
+}
+
+fn build_tree(options: &Options) -> Result<(MerkleTree, u64), String> {
+    let path = match options.file {
+        Some(ref path) => path,
+        None => return Err(USAGE.to_string()),
+    };
+
+    let result = if path == "-" {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        MerkleTree::from_reader(&mut reader, options.chunk_size, options.jobs)
+    } else {
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        MerkleTree::from_reader(&mut file, options.chunk_size, options.jobs)
+    };
+
+    result.map_err(|e| format!("{}: {}", path, e))
+}
+
+fn build_manifest(options: &Options) -> Result<Manifest, String> {
+    let path = match options.file {
+        Some(ref path) => path,
+        None => return Err(USAGE.to_string()),
+    };
+
+    let result = if path == "-" {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        Manifest::from_reader(&mut reader, options.chunk_size, options.jobs)
+    } else {
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        Manifest::from_reader(&mut file, options.chunk_size, options.jobs)
+    };
+
+    result.map_err(|e| format!("{}: {}", path, e))
+}
+
+fn encode(hash: &[u8], format: Format) -> String {
+    match format {
+        Format::Base64 => to_base64(hash),
+        Format::Hex | Format::Json => to_hex_string(hash),
+    }
+}
+
+fn print_root(tree: &MerkleTree, total_len: u64, format: Format) {
+    let root = encode(tree.get_root(), format);
+
+    if format == Format::Json {
+        println!(
+            "{{\"root\":{},\"leaves\":{},\"length\":{}}}",
+            json_string(&root),
+            tree.get_num_leaves(),
+            total_len
+        );
+    } else {
+        println!("{}", root);
+    }
+}
+
+/// Levels from zero level to root, as Display of MerkleTree does
+fn print_tree(tree: &MerkleTree, total_len: u64, format: Format) {
+    let levels: Vec<Vec<String>> = (0..tree.get_num_level())
+        .map(|level| {
+            tree.get_level(level)
+                .chunks(Sha256Hasher::OUTPUT_LEN)
+                .map(|hash| encode(hash, format))
+                .collect()
+        })
+        .collect();
+
+    if format == Format::Json {
+        let levels: Vec<String> = levels
+            .iter()
+            .map(|level| {
+                let hashes: Vec<String> = level.iter().map(|hash| json_string(hash)).collect();
+                format!("[{}]", hashes.join(","))
+            })
+            .collect();
+
+        println!(
+            "{{\"leaves\":{},\"length\":{},\"levels\":[{}]}}",
+            tree.get_num_leaves(),
+            total_len,
+            levels.join(",")
+        );
+    } else {
+        println!("Tree: ");
+
+        for (level, hashes) in levels.iter().enumerate() {
+            println!("Level {}: ", level);
+
+            for (index, hash) in hashes.iter().enumerate() {
+                println!("hash {}: {}", index, hash);
+            }
+
+            println!("--------------------------------------------------------------------");
+        }
+    }
+}
+
+/// JSON string literal of the value
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
 }
@@ -0,0 +1,352 @@
+//! Manifest of a file split into chunks
+//!
+//! Manifest keeps everything needed to check each chunk of the file independently:
+//! chunk size, total length, hash function, shape, hashes of all chunks and the root.
+//! Text format of manifest is stable, fields go in this order one per line:
+//!
+//! merkle-manifest 1
+//! hasher <H::NAME>
+//! shape bitcoin|rfc6962
+//! chunk-size <bytes>
+//! length <bytes>
+//! root <hex>
+//! leaves <number of chunks>
+//!
+//! and then hex hash of each chunk on its own line. Read manifest is checked:
+//! number of chunks matches length and chunk size, and hashes of chunks give the root.
+//! `verify_file` and deserialization (cargo feature `serde`) check the manifest the same way.
+//!
+//! `verify_file` rehashes a file by batches of chunks in parallel (as `MerkleTree::from_reader`)
+//! and reports each corrupt chunk, so only they have to be downloaded again, and the number
+//! of bytes beyond the length of the manifest, which have to be cut off.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use num_cpus;
+use rayon;
+
+use super::{from_hex_string, shared_pool, to_hex_string, MerkleError, MerkleHasher, MerkleTree,
+            Sha256Hasher, TreeShape};
+use dir::field;
+use file::{batch_len, count_chunks, par_chunk_hash, read_batch};
+use shape::hash_leaf;
+
+const MANIFEST_HEADER: &str = "merkle-manifest 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest<H = Sha256Hasher> {
+    pub chunk_size: usize,
+    /// Length of the file in bytes
+    pub total_len: u64,
+    pub shape: TreeShape,
+    /// Hash of each chunk
+    pub leaves: Vec<Vec<u8>>,
+    pub root: Vec<u8>,
+    pub(crate) hasher: PhantomData<H>,
+}
+
+impl<H: MerkleHasher> Manifest<H> {
+    /// Manifest of chunks of the reader, the tree is built as `MerkleTree::from_reader`
+    pub fn from_reader<R: Read>(
+        reader: &mut R,
+        chunk_size: usize,
+        num_cpus: usize,
+    ) -> Result<Manifest<H>, MerkleError> {
+        let (tree, total_len) = MerkleTree::<H>::from_reader(reader, chunk_size, num_cpus)?;
+        Manifest::try_from_tree(&tree, chunk_size, total_len)
+    }
+
+    /// Manifest of chunks of the file using all cpu cores
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        chunk_size: usize,
+    ) -> Result<Manifest<H>, MerkleError> {
+        let mut file = File::open(path)?;
+        Manifest::from_reader(&mut file, chunk_size, num_cpus::get())
+    }
+
+    /// Manifest of the tree built from chunks of `chunk_size` bytes of `total_len` bytes.
+    /// Only the number of chunks is checked, `from_reader` and `from_path` build the tree
+    /// and the manifest together
+    pub fn from_tree(tree: &MerkleTree<H>, chunk_size: usize, total_len: u64) -> Manifest<H> {
+        match Manifest::try_from_tree(tree, chunk_size, total_len) {
+            Ok(manifest) => manifest,
+            Err(e) => panic!("{} in from_tree()", e),
+        }
+    }
+
+    pub fn try_from_tree(
+        tree: &MerkleTree<H>,
+        chunk_size: usize,
+        total_len: u64,
+    ) -> Result<Manifest<H>, MerkleError> {
+        if chunk_size == 0 {
+            return Err(MerkleError::InvalidChunkSize { size: chunk_size });
+        }
+
+        if tree.get_num_leaves() as u64 != cmp::max(count_chunks(total_len, chunk_size), 1) {
+            return Err(MerkleError::SizeOutOfRange {
+                size: tree.get_num_leaves(),
+            });
+        }
+
+        let leaves: Vec<Vec<u8>> = tree.get_level(0)
+            .chunks(H::OUTPUT_LEN)
+            .take(tree.get_num_leaves())
+            .map(|hash| hash.to_vec())
+            .collect();
+
+        Ok(Manifest {
+            chunk_size,
+            total_len,
+            shape: tree.get_shape(),
+            leaves,
+            root: tree.get_root().to_vec(),
+            hasher: PhantomData,
+        })
+    }
+
+    pub fn get_num_chunks(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Check that `bytes` is the chunk `index` of the file
+    pub fn verify_chunk(&self, index: usize, bytes: &[u8]) -> bool {
+        if index >= self.leaves.len() || bytes.len() != self.chunk_len(index) {
+            return false;
+        }
+
+        let mut hashed = vec![0u8; H::OUTPUT_LEN];
+        hash_leaf::<H>(self.shape, bytes, &mut hashed);
+
+        hashed == self.leaves[index]
+    }
+
+    /// Length of chunk `index`, only the last chunk may be shorter than chunk size
+    pub(crate) fn chunk_len(&self, index: usize) -> usize {
+        let start = index as u64 * self.chunk_size as u64;
+        cmp::min(self.total_len.saturating_sub(start), self.chunk_size as u64) as usize
+    }
+
+    /// Check manifest as `read_from` does: chunk size is positive, number of chunks matches
+    /// length, all hashes have length H::OUTPUT_LEN and hashes of chunks give the root.
+    /// Error has the line of text format where the inconsistent field is
+    pub(crate) fn check(&self) -> Result<(), MerkleError> {
+        if self.chunk_size == 0 {
+            return Err(MerkleError::InvalidManifest { line: 4 });
+        }
+
+        if self.root.len() != H::OUTPUT_LEN {
+            return Err(MerkleError::InvalidManifest { line: 6 });
+        }
+
+        // Empty file is one empty chunk
+        if self.leaves.len() as u64 != cmp::max(count_chunks(self.total_len, self.chunk_size), 1) {
+            return Err(MerkleError::InvalidManifest { line: 7 });
+        }
+
+        let mut base: Vec<u8> = Vec::with_capacity(self.leaves.len() * H::OUTPUT_LEN);
+
+        for (index, hash) in self.leaves.iter().enumerate() {
+            if hash.len() != H::OUTPUT_LEN {
+                return Err(MerkleError::InvalidManifest { line: 8 + index });
+            }
+
+            base.extend_from_slice(hash);
+        }
+
+        let tree: MerkleTree<H> =
+            MerkleTree::from_zero_level_in(base, self.leaves.len(), None, self.shape);
+
+        if tree.get_root() != self.root.as_slice() {
+            return Err(MerkleError::InvalidManifest { line: 6 });
+        }
+
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MerkleError> {
+        writeln!(writer, "{}", MANIFEST_HEADER)?;
+        writeln!(writer, "hasher {}", H::NAME)?;
+        writeln!(writer, "shape {}", shape_name(self.shape))?;
+        writeln!(writer, "chunk-size {}", self.chunk_size)?;
+        writeln!(writer, "length {}", self.total_len)?;
+        writeln!(writer, "root {}", to_hex_string(&self.root))?;
+        writeln!(writer, "leaves {}", self.leaves.len())?;
+
+        for hash in &self.leaves {
+            writeln!(writer, "{}", to_hex_string(hash))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Manifest<H>, MerkleError> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(MerkleError::InvalidManifest { line: 1 });
+        }
+
+        if field(lines.next(), "hasher") != Some(H::NAME) {
+            return Err(MerkleError::InvalidManifest { line: 2 });
+        }
+
+        let shape = match field(lines.next(), "shape") {
+            Some("bitcoin") => TreeShape::Bitcoin,
+            Some("rfc6962") => TreeShape::Rfc6962,
+            _ => return Err(MerkleError::InvalidManifest { line: 3 }),
+        };
+
+        let chunk_size = match field(lines.next(), "chunk-size").and_then(|v| v.parse().ok()) {
+            Some(size) if size > 0 => size,
+            _ => return Err(MerkleError::InvalidManifest { line: 4 }),
+        };
+
+        let total_len: u64 = match field(lines.next(), "length").and_then(|v| v.parse().ok()) {
+            Some(len) => len,
+            None => return Err(MerkleError::InvalidManifest { line: 5 }),
+        };
+
+        let root = match field(lines.next(), "root").and_then(from_hex_string) {
+            Some(ref root) if root.len() == H::OUTPUT_LEN => root.clone(),
+            _ => return Err(MerkleError::InvalidManifest { line: 6 }),
+        };
+
+        let num_leaves: usize = match field(lines.next(), "leaves").and_then(|v| v.parse().ok()) {
+            Some(num) if num as u64 == cmp::max(count_chunks(total_len, chunk_size), 1) => num,
+            _ => return Err(MerkleError::InvalidManifest { line: 7 }),
+        };
+
+        let mut leaves: Vec<Vec<u8>> = Vec::new();
+
+        for index in 0..num_leaves {
+            match lines.next().and_then(from_hex_string) {
+                Some(hash) if hash.len() == H::OUTPUT_LEN => leaves.push(hash),
+                _ => return Err(MerkleError::InvalidManifest { line: 8 + index }),
+            }
+        }
+
+        if lines.next().is_some() {
+            return Err(MerkleError::InvalidManifest {
+                line: 8 + num_leaves,
+            });
+        }
+
+        let manifest = Manifest {
+            chunk_size,
+            total_len,
+            shape,
+            leaves,
+            root,
+            hasher: PhantomData,
+        };
+
+        manifest.check()?;
+        Ok(manifest)
+    }
+}
+
+/// Result of checking a file against its manifest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Indices of chunks which do not match the manifest in increasing order
+    pub corrupt: Vec<usize>,
+    /// Number of bytes of the file beyond the length in the manifest
+    pub extra_bytes: u64,
+}
+
+impl VerifyReport {
+    pub fn is_intact(&self) -> bool {
+        self.corrupt.is_empty() && self.extra_bytes == 0
+    }
+}
+
+/// Chunks of the file which do not match the manifest and extra bytes of the file.
+/// Chunks missing in a shorter file are corrupt, bytes of a longer file after the length
+/// of the manifest are only counted. Inconsistent manifest is `InvalidManifest` (see `read_from`)
+pub fn verify_file<H: MerkleHasher, P: AsRef<Path>>(
+    path: P,
+    manifest: &Manifest<H>,
+    num_cpus: usize,
+) -> Result<VerifyReport, MerkleError> {
+    let pool = shared_pool(num_cpus)?;
+
+    verify_file_in(path, manifest, Some(&pool))
+}
+
+pub fn verify_file_in<H: MerkleHasher, P: AsRef<Path>>(
+    path: P,
+    manifest: &Manifest<H>,
+    pool: Option<&rayon::ThreadPool>,
+) -> Result<VerifyReport, MerkleError> {
+    manifest.check()?;
+
+    let file = File::open(path)?;
+    let extra_bytes = file.metadata()?.len().saturating_sub(manifest.total_len);
+
+    // Chunks are read up to the length of the manifest, so the last one is never longer
+    let mut reader = file.take(manifest.total_len);
+
+    let chunk_size = manifest.chunk_size;
+    let num_leaves = manifest.leaves.len();
+
+    let batch_len = batch_len(chunk_size)?;
+    let mut batch: Vec<u8> = Vec::new();
+    let mut hashes = vec![0u8; batch_len / chunk_size * H::OUTPUT_LEN];
+
+    let mut corrupt: Vec<usize> = Vec::new();
+    let mut index = 0;
+    let mut total_len: u64 = 0;
+
+    loop {
+        let len = read_batch(&mut reader, &mut batch, batch_len)?;
+        total_len += len as u64;
+
+        let count = count_chunks(len as u64, chunk_size) as usize;
+        let hashes = &mut hashes[..count * H::OUTPUT_LEN];
+        par_chunk_hash::<H>(&batch[..len], chunk_size, hashes, pool, manifest.shape);
+
+        for (i, hash) in (index..).zip(hashes.chunks(H::OUTPUT_LEN)) {
+            if hash != manifest.leaves[i].as_slice() {
+                corrupt.push(i);
+            }
+        }
+
+        index += count;
+
+        if len < batch_len {
+            break;
+        }
+    }
+
+    // Empty file is one empty chunk
+    if total_len == 0 {
+        if !manifest.verify_chunk(0, &[]) {
+            corrupt.push(0);
+        }
+
+        index = 1;
+    }
+
+    corrupt.extend(index..num_leaves);
+
+    Ok(VerifyReport {
+        corrupt,
+        extra_bytes,
+    })
+}
+
+fn shape_name(shape: TreeShape) -> &'static str {
+    match shape {
+        TreeShape::Bitcoin => "bitcoin",
+        TreeShape::Rfc6962 => "rfc6962",
+    }
+}
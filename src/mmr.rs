@@ -0,0 +1,272 @@
+//! Merkle Mountain Range - append-only accumulator
+//!
+//! Level `level` keeps hashes of all complete subtrees of 2^level leaves from left to right,
+//! so appended leaf costs one hash_leaf and on average one hash_node. Leaves are H::hash_leaf
+//! of blocks and nodes are H::hash_node(left || right) as in MerkleTree.
+//!
+//! MMR of `size` leaves is a list of perfect trees (peaks) for each set bit of size,
+//! from the highest bit (leftmost leaves) to the lowest. Root bags peaks from right to left:
+//! root = hash_node(peak_0 || hash_node(peak_1 || ... hash_node(peak_n-1 || peak_n))).
+//! Peaks of any previous size are kept in levels, so proofs may be made against them.
+
+use std::marker::PhantomData;
+
+use super::{MerkleError, MerkleHasher, Sha256Hasher, TreeShape};
+use shape::hash_pair;
+
+/// Proof that leaf with index `index` belongs to MMR of `size` leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct MmrProof<H = Sha256Hasher> {
+    pub index: usize,
+    pub size: usize,
+    /// Siblings on the path from leaf to its peak
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub path: Vec<Vec<u8>>,
+    /// Other peaks from left to right
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub peaks: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+pub struct Mmr<H = Sha256Hasher> {
+    levels: Vec<Vec<u8>>,
+    num_leaves: usize,
+    hasher: PhantomData<H>,
+}
+
+impl Mmr {
+    pub fn new() -> Mmr {
+        Mmr::default()
+    }
+}
+
+impl<H: MerkleHasher> Default for Mmr<H> {
+    fn default() -> Mmr<H> {
+        Mmr {
+            levels: vec![Vec::new()],
+            num_leaves: 0,
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: MerkleHasher> Mmr<H> {
+    /// Append one block
+    pub fn push(&mut self, block: &[u8]) {
+        let size_hash = H::OUTPUT_LEN;
+
+        let start = self.levels[0].len();
+        self.levels[0].resize(start + size_hash, 0u8);
+        H::hash_leaf(block, &mut self.levels[0][start..]);
+
+        self.num_leaves += 1;
+
+        // Each set low bit of the new size is a completed subtree
+        let mut level = 0;
+
+        while self.num_leaves >> level & 1 == 0 {
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+
+            let mut hashed = vec![0u8; size_hash];
+            {
+                let children = &self.levels[level];
+                H::hash_node(&children[children.len() - 2 * size_hash..], &mut hashed);
+            }
+
+            self.levels[level + 1].extend_from_slice(&hashed);
+            level += 1;
+        }
+    }
+
+    /// Append blocks
+    pub fn extend<B: AsRef<[u8]>>(&mut self, blocks: &[B]) {
+        for block in blocks {
+            self.push(block.as_ref());
+        }
+    }
+
+    pub fn get_num_leaves(&self) -> usize {
+        self.num_leaves
+    }
+
+    /// Peaks of MMR from left to right
+    pub fn get_peaks(&self) -> Vec<&[u8]> {
+        self.peaks_at(self.num_leaves)
+    }
+
+    pub fn get_root(&self) -> Vec<u8> {
+        match self.try_get_root() {
+            Ok(root) => root,
+            Err(e) => panic!("{} in get_root()", e),
+        }
+    }
+
+    pub fn try_get_root(&self) -> Result<Vec<u8>, MerkleError> {
+        self.try_get_root_at(self.num_leaves)
+    }
+
+    /// Root of MMR of the first `size` leaves
+    pub fn get_root_at(&self, size: usize) -> Vec<u8> {
+        match self.try_get_root_at(size) {
+            Ok(root) => root,
+            Err(e) => panic!("{} in get_root_at()", e),
+        }
+    }
+
+    pub fn try_get_root_at(&self, size: usize) -> Result<Vec<u8>, MerkleError> {
+        if size == 0 || size > self.num_leaves {
+            return Err(MerkleError::SizeOutOfRange { size });
+        }
+
+        let peaks: Vec<Vec<u8>> = self.peaks_at(size)
+            .into_iter()
+            .map(|peak| peak.to_vec())
+            .collect();
+
+        Ok(bag_peaks::<H>(&peaks))
+    }
+
+    /// Proof for leaf `index` against MMR of the first `size` leaves
+    pub fn prove(&self, index: usize, size: usize) -> MmrProof<H> {
+        match self.try_prove(index, size) {
+            Ok(proof) => proof,
+            Err(e) => panic!("{} in prove()", e),
+        }
+    }
+
+    pub fn try_prove(&self, index: usize, size: usize) -> Result<MmrProof<H>, MerkleError> {
+        if size == 0 || size > self.num_leaves {
+            return Err(MerkleError::SizeOutOfRange { size });
+        }
+
+        if index >= size {
+            return Err(MerkleError::IndexOutOfRange { index });
+        }
+
+        let size_hash = H::OUTPUT_LEN;
+        let peaks = peak_ranges(size);
+        let k = peak_of(&peaks, index);
+        let (height, start) = peaks[k];
+
+        let mut path: Vec<Vec<u8>> = Vec::with_capacity(height);
+        let mut i = index - start;
+
+        for level in 0..height {
+            let sibling = ((start >> level) + (i ^ 1)) * size_hash;
+            path.push(self.levels[level][sibling..sibling + size_hash].to_vec());
+            i >>= 1;
+        }
+
+        let mut other_peaks: Vec<Vec<u8>> = self.peaks_at(size)
+            .into_iter()
+            .map(|peak| peak.to_vec())
+            .collect();
+        other_peaks.remove(k);
+
+        Ok(MmrProof {
+            index,
+            size,
+            path,
+            peaks: other_peaks,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Peaks of MMR of the first `size` leaves, `size` is not greater than number of leaves
+    fn peaks_at(&self, size: usize) -> Vec<&[u8]> {
+        let size_hash = H::OUTPUT_LEN;
+
+        peak_ranges(size)
+            .into_iter()
+            .map(|(height, start)| {
+                let offset = (start >> height) * size_hash;
+                &self.levels[height][offset..offset + size_hash]
+            })
+            .collect()
+    }
+}
+
+/// Check that block `leaf_data` is the leaf `proof.index` of MMR of `size` leaves with root
+/// `root`. Size is not taken from the proof: root of MMR with one peak is the peak itself,
+/// so a proof for a smaller size could prove an inner node as a leaf
+pub fn verify_mmr<H: MerkleHasher>(
+    root: &[u8],
+    size: usize,
+    leaf_data: &[u8],
+    proof: &MmrProof<H>,
+) -> bool {
+    if proof.size != size || proof.index >= proof.size {
+        return false;
+    }
+
+    let peaks = peak_ranges(proof.size);
+    let k = peak_of(&peaks, proof.index);
+    let (height, start) = peaks[k];
+
+    if proof.path.len() != height || proof.peaks.len() + 1 != peaks.len()
+        || proof
+            .path
+            .iter()
+            .chain(proof.peaks.iter())
+            .any(|hash| hash.len() != H::OUTPUT_LEN)
+    {
+        return false;
+    }
+
+    let mut hash = vec![0u8; H::OUTPUT_LEN];
+    H::hash_leaf(leaf_data, &mut hash);
+
+    let mut i = proof.index - start;
+
+    for sibling in &proof.path {
+        hash = if i & 1 == 1 {
+            hash_pair::<H>(TreeShape::Bitcoin, sibling, &hash)
+        } else {
+            hash_pair::<H>(TreeShape::Bitcoin, &hash, sibling)
+        };
+        i >>= 1;
+    }
+
+    let mut all_peaks = proof.peaks.clone();
+    all_peaks.insert(k, hash);
+
+    bag_peaks::<H>(&all_peaks).as_slice() == root
+}
+
+/// Height and first leaf of each peak of MMR of `size` leaves from left to right
+fn peak_ranges(size: usize) -> Vec<(usize, usize)> {
+    let mut peaks: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0;
+
+    for height in (0..64).rev() {
+        if (size as u64) >> height & 1 == 1 {
+            peaks.push((height, start));
+            start += 1 << height;
+        }
+    }
+
+    peaks
+}
+
+/// Index of the peak which covers leaf `index`
+fn peak_of(peaks: &[(usize, usize)], index: usize) -> usize {
+    peaks
+        .iter()
+        .position(|&(height, start)| index < start + (1 << height))
+        .unwrap()
+}
+
+/// Bag peaks from right to left
+fn bag_peaks<H: MerkleHasher>(peaks: &[Vec<u8>]) -> Vec<u8> {
+    let mut root = peaks[peaks.len() - 1].clone();
+
+    for peak in peaks[..peaks.len() - 1].iter().rev() {
+        root = hash_pair::<H>(TreeShape::Bitcoin, peak, &root);
+    }
+
+    root
+}
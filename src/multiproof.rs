@@ -0,0 +1,164 @@
+//! Inclusion proofs of many leaves at once
+//!
+//! Proof contains only siblings which verifier can not compute itself: if both children of
+//! a node are on the paths of proved leaves then none of them is in the proof.
+//! Siblings are ordered by level (from zero level) and by index inside the level.
+//!
+//! Copies of last block (TreeShape::Bitcoin) are never in the proof: hash without right sibling
+//! is hashed with itself (Bitcoin) or moved up unchanged (Rfc6962) by verifier.
+
+use std::marker::PhantomData;
+
+use super::{MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+use shape::{checked_level_sizes, hash_children, hash_leaf};
+
+/// Proof that leaves with indices `indices` belong to the tree built from `tree_size` blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct MultiProof<H = Sha256Hasher> {
+    /// Proved leaves in increasing order without repeats
+    pub indices: Vec<usize>,
+    pub tree_size: usize,
+    pub shape: TreeShape,
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub hashes: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Proof for many leaves, indices may be in any order and repeated
+    pub fn prove_many(&self, indices: &[usize]) -> MultiProof<H> {
+        match self.try_prove_many(indices) {
+            Ok(proof) => proof,
+            Err(e) => panic!("{} in prove_many()", e),
+        }
+    }
+
+    pub fn try_prove_many(&self, indices: &[usize]) -> Result<MultiProof<H>, MerkleError> {
+        if indices.is_empty() {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        if let Some(&index) = indices.iter().find(|&&index| index >= self.num_leaves) {
+            return Err(MerkleError::IndexOutOfRange { index });
+        }
+
+        let mut known: Vec<usize> = indices.to_vec();
+        known.sort();
+        known.dedup();
+
+        let proved = known.clone();
+        let mut hashes: Vec<Vec<u8>> = Vec::new();
+
+        // Number of hashes of level without the copy of last block
+        let mut num_block = self.num_leaves;
+
+        for level in 0..self.tree.len() - 1 {
+            for (j, &i) in known.iter().enumerate() {
+                let sibling = i ^ 1;
+
+                let sibling_known = if i.is_multiple_of(2) {
+                    known.get(j + 1) == Some(&sibling)
+                } else {
+                    j > 0 && known[j - 1] == sibling
+                };
+
+                if !sibling_known && sibling < num_block {
+                    hashes.push(self.get_hash(level, sibling).to_vec());
+                }
+            }
+
+            known = known.iter().map(|&i| i / 2).collect();
+            known.dedup();
+
+            num_block = num_block / 2 + num_block % 2;
+        }
+
+        Ok(MultiProof {
+            indices: proved,
+            tree_size: self.num_leaves,
+            shape: self.shape,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+}
+
+/// Recompute root from data of leaves (in order of `proof.indices`) and proof
+/// and compare it with the expected root of the tree built from `tree_size` blocks with
+/// the given shape. Size and shape are not taken from the proof (see `verify_inclusion`)
+pub fn verify_many<H: MerkleHasher, B: AsRef<[u8]>>(
+    root: &[u8],
+    tree_size: usize,
+    shape: TreeShape,
+    leaves_data: &[B],
+    proof: &MultiProof<H>,
+) -> bool {
+    if proof.tree_size != tree_size || proof.shape != shape || proof.indices.is_empty()
+        || leaves_data.len() != proof.indices.len()
+        || proof.indices.windows(2).any(|pair| pair[0] >= pair[1])
+        || proof.indices[proof.indices.len() - 1] >= proof.tree_size
+        || proof.hashes.iter().any(|hash| hash.len() != H::OUTPUT_LEN)
+    {
+        return false;
+    }
+
+    let mut current: Vec<(usize, Vec<u8>)> = proof
+        .indices
+        .iter()
+        .zip(leaves_data.iter())
+        .map(|(&index, data)| {
+            let mut hashed = vec![0u8; H::OUTPUT_LEN];
+            hash_leaf::<H>(shape, data.as_ref(), &mut hashed);
+            (index, hashed)
+        })
+        .collect();
+
+    let mut hashes = proof.hashes.iter();
+    let mut num_block = tree_size;
+    let num_level = match checked_level_sizes(tree_size, shape) {
+        Some(sizes) => sizes.len(),
+        None => return false,
+    };
+
+    for _ in 0..num_level - 1 {
+        let mut next: Vec<(usize, Vec<u8>)> = Vec::with_capacity(current.len());
+        let mut nodes = current.into_iter().peekable();
+
+        while let Some((i, hashed)) = nodes.next() {
+            let mut input: Vec<u8> = Vec::with_capacity(2 * H::OUTPUT_LEN);
+
+            if i % 2 == 1 {
+                match hashes.next() {
+                    Some(sibling) => input.extend_from_slice(sibling),
+                    None => return false,
+                }
+                input.extend_from_slice(&hashed);
+            } else {
+                input.extend_from_slice(&hashed);
+
+                if nodes.peek().map(|&(j, _)| j) == Some(i + 1) {
+                    input.extend_from_slice(&nodes.next().unwrap().1);
+                } else if i + 1 < num_block {
+                    match hashes.next() {
+                        Some(sibling) => input.extend_from_slice(sibling),
+                        None => return false,
+                    }
+                } else if shape == TreeShape::Bitcoin {
+                    // Hash without right sibling is hashed with its copy
+                    input.extend_from_slice(&hashed);
+                }
+            }
+
+            let mut parent = vec![0u8; H::OUTPUT_LEN];
+            hash_children::<H>(shape, &input, &mut parent);
+            next.push((i / 2, parent));
+        }
+
+        current = next;
+        num_block = num_block / 2 + num_block % 2;
+    }
+
+    hashes.next().is_none() && current.len() == 1 && current[0].1.as_slice() == root
+}
@@ -0,0 +1,137 @@
+//! Inclusion (audit) proofs of Merkle hash tree
+//!
+//! Proof for leaf is a list of sibling hashes on the path from zero level to root.
+//! Each sibling knows its side, so verifier concatenates hashes in the same order as the tree does.
+//! The copy of last block (for levels with odd number of blocks) is an ordinary sibling here:
+//! the last block of such level is proved by its own copy.
+//! For TreeShape::Rfc6962 the block moved up unchanged has no sibling, so the path is the
+//! audit path of RFC 6962.
+
+use std::marker::PhantomData;
+
+use super::{MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+use shape::{checked_level_sizes, hash_children, hash_leaf};
+
+/// Side of sibling hash relative to the hash on the proved path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Proof that leaf with index `index` belongs to the tree built from `tree_size` blocks
+/// with hash function H
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct InclusionProof<H = Sha256Hasher> {
+    pub index: usize,
+    pub tree_size: usize,
+    pub shape: TreeShape,
+    /// Sibling hashes ordered from zero level to the level below root
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::path"))]
+    pub path: Vec<(Side, Vec<u8>)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) hasher: PhantomData<H>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    pub fn prove(&self, index: usize) -> InclusionProof<H> {
+        match self.try_prove(index) {
+            Ok(proof) => proof,
+            Err(e) => panic!("{} in prove()", e),
+        }
+    }
+
+    pub fn try_prove(&self, index: usize) -> Result<InclusionProof<H>, MerkleError> {
+        if index > self.num_leaves - 1 {
+            return Err(MerkleError::IndexOutOfRange { index });
+        }
+
+        let mut path: Vec<(Side, Vec<u8>)> = Vec::with_capacity(self.tree.len() - 1);
+        let mut i = index;
+
+        for level in 0..self.tree.len() - 1 {
+            let num_block = self.tree[level].len() / H::OUTPUT_LEN;
+
+            if i ^ 1 < num_block {
+                let side = if i.is_multiple_of(2) { Side::Right } else { Side::Left };
+                path.push((side, self.get_hash(level, i ^ 1).to_vec()));
+            }
+
+            i /= 2;
+        }
+
+        Ok(InclusionProof {
+            index,
+            tree_size: self.num_leaves,
+            shape: self.shape,
+            path,
+            hasher: PhantomData,
+        })
+    }
+}
+
+/// Recompute root from leaf data and proof and compare it with the expected root
+/// of the tree built from `tree_size` blocks with the given shape.
+/// Size and shape must come from the verifier, not from the proof: an inner node of a bigger
+/// tree is also a leaf hash (of the concatenation of its children), so a proof for a smaller
+/// tree or for the other shape would prove data which is not a block of the tree
+pub fn verify_inclusion<H: MerkleHasher>(
+    root: &[u8],
+    tree_size: usize,
+    shape: TreeShape,
+    leaf_data: &[u8],
+    proof: &InclusionProof<H>,
+) -> bool {
+    if proof.tree_size != tree_size || proof.shape != shape || proof.index >= proof.tree_size {
+        return false;
+    }
+
+    let sizes = match checked_level_sizes(tree_size, shape) {
+        Some(sizes) => sizes,
+        None => return false,
+    };
+
+    let mut hashed = vec![0u8; H::OUTPUT_LEN];
+    hash_leaf::<H>(shape, leaf_data, &mut hashed);
+
+    let mut i = proof.index;
+    let mut path = proof.path.iter();
+    let mut input: Vec<u8> = Vec::with_capacity(2 * H::OUTPUT_LEN);
+
+    for &num_block in &sizes[..sizes.len() - 1] {
+        // Hash without sibling is moved up unchanged
+        if i ^ 1 < num_block {
+            let (side, sibling) = match path.next() {
+                Some(&(side, ref sibling)) => (side, sibling),
+                None => return false,
+            };
+
+            if sibling.len() != H::OUTPUT_LEN {
+                return false;
+            }
+
+            input.clear();
+
+            // Side of sibling must agree with position of hash on the path
+            match side {
+                Side::Left if i % 2 == 1 => {
+                    input.extend_from_slice(sibling);
+                    input.extend_from_slice(&hashed);
+                }
+                Side::Right if i.is_multiple_of(2) => {
+                    input.extend_from_slice(&hashed);
+                    input.extend_from_slice(sibling);
+                }
+                _ => return false,
+            }
+
+            hash_children::<H>(shape, &input, &mut hashed);
+        }
+
+        i /= 2;
+    }
+
+    path.next().is_none() && hashed.as_slice() == root
+}
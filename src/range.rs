@@ -0,0 +1,175 @@
+//! Range proofs of Merkle hash tree
+//!
+//! Proof for contiguous leaves from `start` to `end` (exclusive): on each level the hashes
+//! computed from the range are contiguous, so only the left neighbour of the first hash
+//! and the right neighbour of the last hash may be needed. They are kept in `left` and
+//! `right` respectively, ordered from zero level to root.
+//!
+//! Copy of last block (TreeShape::Bitcoin) is never in the proof, verifier makes it
+//! as `copy_last_data` does. For TreeShape::Rfc6962 last hash without right neighbour
+//! is moved up unchanged.
+
+use std::marker::PhantomData;
+
+use super::{MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape};
+use shape::{checked_level_sizes, hash_children, hash_leaf};
+
+/// Proof that leaves from `start` to `end` (exclusive) belong to the tree built from
+/// `tree_size` blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct RangeProof<H = Sha256Hasher> {
+    pub start: usize,
+    pub end: usize,
+    pub tree_size: usize,
+    pub shape: TreeShape,
+    /// Left boundary siblings ordered from zero level to the level below root
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub left: Vec<Vec<u8>>,
+    /// Right boundary siblings ordered from zero level to the level below root
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub right: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    pub fn prove_range(&self, start: usize, end: usize) -> RangeProof<H> {
+        match self.try_prove_range(start, end) {
+            Ok(proof) => proof,
+            Err(e) => panic!("{} in prove_range()", e),
+        }
+    }
+
+    pub fn try_prove_range(&self, start: usize, end: usize) -> Result<RangeProof<H>, MerkleError> {
+        if end > self.num_leaves {
+            return Err(MerkleError::IndexOutOfRange { index: end });
+        }
+
+        if start >= end {
+            return Err(MerkleError::EmptyInput);
+        }
+
+        let mut left: Vec<Vec<u8>> = Vec::new();
+        let mut right: Vec<Vec<u8>> = Vec::new();
+
+        let mut first = start;
+        let mut last = end;
+
+        // Number of hashes of level without the copy of last block
+        let mut num_block = self.num_leaves;
+
+        for level in 0..self.tree.len() - 1 {
+            if first % 2 == 1 {
+                first -= 1;
+                left.push(self.get_hash(level, first).to_vec());
+            }
+
+            if (last - first) % 2 == 1 && last < num_block {
+                right.push(self.get_hash(level, last).to_vec());
+                last += 1;
+            }
+
+            first /= 2;
+            last = last / 2 + last % 2;
+            num_block = num_block / 2 + num_block % 2;
+        }
+
+        Ok(RangeProof {
+            start,
+            end,
+            tree_size: self.num_leaves,
+            shape: self.shape,
+            left,
+            right,
+            hasher: PhantomData,
+        })
+    }
+}
+
+/// Recompute root from data of leaves from `proof.start` to `proof.end` and proof
+/// and compare it with the expected root of the tree built from `tree_size` blocks with
+/// the given shape. Size and shape are not taken from the proof (see `verify_inclusion`)
+pub fn verify_range<H: MerkleHasher, B: AsRef<[u8]>>(
+    root: &[u8],
+    tree_size: usize,
+    shape: TreeShape,
+    leaves_data: &[B],
+    proof: &RangeProof<H>,
+) -> bool {
+    if proof.tree_size != tree_size || proof.shape != shape || proof.start >= proof.end
+        || proof.end > proof.tree_size
+        || leaves_data.len() != proof.end - proof.start
+        || proof
+            .left
+            .iter()
+            .chain(proof.right.iter())
+            .any(|hash| hash.len() != H::OUTPUT_LEN)
+    {
+        return false;
+    }
+
+    let mut hashes: Vec<Vec<u8>> = leaves_data
+        .iter()
+        .map(|data| {
+            let mut hashed = vec![0u8; H::OUTPUT_LEN];
+            hash_leaf::<H>(shape, data.as_ref(), &mut hashed);
+            hashed
+        })
+        .collect();
+
+    let mut left = proof.left.iter();
+    let mut right = proof.right.iter();
+
+    let mut first = proof.start;
+    let mut num_block = tree_size;
+    let num_level = match checked_level_sizes(tree_size, shape) {
+        Some(sizes) => sizes.len(),
+        None => return false,
+    };
+
+    for _ in 0..num_level - 1 {
+        if first % 2 == 1 {
+            match left.next() {
+                Some(sibling) => hashes.insert(0, sibling.clone()),
+                None => return false,
+            }
+            first -= 1;
+        }
+
+        if hashes.len() % 2 == 1 {
+            if first + hashes.len() < num_block {
+                match right.next() {
+                    Some(sibling) => hashes.push(sibling.clone()),
+                    None => return false,
+                }
+            } else if shape == TreeShape::Bitcoin {
+                let copy = hashes[hashes.len() - 1].clone();
+                hashes.push(copy);
+            }
+        }
+
+        let mut input: Vec<u8> = Vec::with_capacity(2 * H::OUTPUT_LEN);
+
+        hashes = hashes
+            .chunks(2)
+            .map(|children| {
+                input.clear();
+
+                for child in children {
+                    input.extend_from_slice(child);
+                }
+
+                let mut parent = vec![0u8; H::OUTPUT_LEN];
+                hash_children::<H>(shape, &input, &mut parent);
+                parent
+            })
+            .collect();
+
+        first /= 2;
+        num_block = num_block / 2 + num_block % 2;
+    }
+
+    left.next().is_none() && right.next().is_none() && hashes.len() == 1
+        && hashes[0].as_slice() == root
+}
@@ -0,0 +1,262 @@
+//! Serde support of Merkle hash tree (cargo feature `serde`)
+//!
+//! Hashes are hex strings (as `to_hex_string`) in human-readable formats like JSON
+//! and raw bytes in binary formats like CBOR. Tree is serialized with id of hash function,
+//! shape, number of leaves and all levels, manifest with id of hash function too.
+//! Deserialized tree is checked as in `read_from`, deserialized manifest too.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, SerializeStruct, Serializer};
+
+use super::{from_hex_string, to_hex_string, Manifest, MerkleError, MerkleHasher, MerkleTree,
+            Side, TreeShape};
+
+/// Borrowed hash for serialization
+struct HashRef<'a>(&'a [u8]);
+
+/// Owned hash for deserialization
+struct HashBuf(Vec<u8>);
+
+impl<'a> Serialize for HashRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&to_hex_string(self.0))
+        } else {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+}
+
+/// Borrowed list of hashes for serialization
+struct HashesRef<'a>(&'a [Vec<u8>]);
+
+impl<'a> Serialize for HashesRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+
+        for hash in self.0 {
+            seq.serialize_element(&HashRef(hash))?;
+        }
+
+        seq.end()
+    }
+}
+
+struct HashVisitor;
+
+impl<'de> Visitor<'de> for HashVisitor {
+    type Value = HashBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "hash as hex string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<HashBuf, E> {
+        match from_hex_string(value) {
+            Some(bytes) => Ok(HashBuf(bytes)),
+            None => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+        }
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<HashBuf, E> {
+        Ok(HashBuf(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<HashBuf, E> {
+        Ok(HashBuf(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<HashBuf, A::Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(HashBuf(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for HashBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<HashBuf, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HashVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(HashVisitor)
+        }
+    }
+}
+
+/// `#[serde(with = "hashes")]` for a list of hashes
+pub(crate) mod hashes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(hashes: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        HashesRef(hashes).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        let hashes: Vec<HashBuf> = Deserialize::deserialize(deserializer)?;
+        Ok(hashes.into_iter().map(|hash| hash.0).collect())
+    }
+}
+
+/// `#[serde(with = "path")]` for a list of sibling hashes with their sides
+pub(crate) mod path {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        path: &[(Side, Vec<u8>)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(path.len()))?;
+
+        for &(side, ref hash) in path {
+            seq.serialize_element(&(side, HashRef(hash)))?;
+        }
+
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(Side, Vec<u8>)>, D::Error> {
+        let path: Vec<(Side, HashBuf)> = Deserialize::deserialize(deserializer)?;
+        Ok(path.into_iter().map(|(side, hash)| (side, hash.0)).collect())
+    }
+}
+
+/// Tree level as a list of hashes
+struct LevelRef<'a>(&'a [u8], usize);
+
+impl<'a> Serialize for LevelRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len() / self.1))?;
+
+        for hash in self.0.chunks(self.1) {
+            seq.serialize_element(&HashRef(hash))?;
+        }
+
+        seq.end()
+    }
+}
+
+/// Tree levels from zero level to root
+struct LevelsRef<'a>(&'a [Vec<u8>], usize);
+
+impl<'a> Serialize for LevelsRef<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+
+        for level in self.0 {
+            seq.serialize_element(&LevelRef(level, self.1))?;
+        }
+
+        seq.end()
+    }
+}
+
+impl<H: MerkleHasher> Serialize for MerkleTree<H> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("MerkleTree", 4)?;
+        state.serialize_field("hasher", &H::ID)?;
+        state.serialize_field("shape", &self.shape)?;
+        state.serialize_field("num_leaves", &self.num_leaves)?;
+        state.serialize_field("levels", &LevelsRef(&self.tree, H::OUTPUT_LEN))?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "MerkleTree")]
+struct TreeRepr {
+    hasher: u8,
+    shape: TreeShape,
+    num_leaves: usize,
+    levels: Vec<Vec<HashBuf>>,
+}
+
+impl<'de, H: MerkleHasher> Deserialize<'de> for MerkleTree<H> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MerkleTree<H>, D::Error> {
+        let repr = TreeRepr::deserialize(deserializer)?;
+
+        if repr.hasher != H::ID {
+            return Err(de::Error::custom(MerkleError::HasherMismatch {
+                expected: H::ID,
+                found: repr.hasher,
+            }));
+        }
+
+        let mut tree: Vec<Vec<u8>> = Vec::with_capacity(repr.levels.len());
+
+        for (index, level) in repr.levels.into_iter().enumerate() {
+            let mut hashes: Vec<u8> = Vec::with_capacity(level.len() * H::OUTPUT_LEN);
+
+            for hash in level {
+                if hash.0.len() != H::OUTPUT_LEN {
+                    return Err(de::Error::custom(MerkleError::InvalidLevel { level: index }));
+                }
+
+                hashes.extend_from_slice(&hash.0);
+            }
+
+            tree.push(hashes);
+        }
+
+        MerkleTree::from_levels(tree, repr.num_leaves, repr.shape).map_err(de::Error::custom)
+    }
+}
+
+impl<H: MerkleHasher> Serialize for Manifest<H> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Manifest", 6)?;
+        state.serialize_field("hasher", &H::ID)?;
+        state.serialize_field("chunk_size", &self.chunk_size)?;
+        state.serialize_field("total_len", &self.total_len)?;
+        state.serialize_field("shape", &self.shape)?;
+        state.serialize_field("leaves", &HashesRef(&self.leaves))?;
+        state.serialize_field("root", &HashRef(&self.root))?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Manifest")]
+struct ManifestRepr {
+    hasher: u8,
+    chunk_size: usize,
+    total_len: u64,
+    shape: TreeShape,
+    leaves: Vec<HashBuf>,
+    root: HashBuf,
+}
+
+impl<'de, H: MerkleHasher> Deserialize<'de> for Manifest<H> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Manifest<H>, D::Error> {
+        let repr = ManifestRepr::deserialize(deserializer)?;
+
+        if repr.hasher != H::ID {
+            return Err(de::Error::custom(MerkleError::HasherMismatch {
+                expected: H::ID,
+                found: repr.hasher,
+            }));
+        }
+
+        let manifest = Manifest {
+            chunk_size: repr.chunk_size,
+            total_len: repr.total_len,
+            shape: repr.shape,
+            leaves: repr.leaves.into_iter().map(|hash| hash.0).collect(),
+            root: repr.root.0,
+            hasher: PhantomData,
+        };
+
+        manifest.check().map_err(de::Error::custom)?;
+        Ok(manifest)
+    }
+}
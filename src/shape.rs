@@ -0,0 +1,100 @@
+//! Shapes of Merkle hash tree
+//!
+//! Bitcoin: if tree level has odd number of blocks then last block is copied (see crate docs).
+//! Zero level uses H::hash_leaf and other levels use H::hash_node.
+//!
+//! Rfc6962 (Certificate Transparency): nothing is copied, last block of level with odd number
+//! of blocks is moved up to the next level unchanged. It gives the same tree as unbalanced
+//! left-heavy splitting of RFC 6962. Hash of zero level is H::digest(0x00 || block),
+//! hash of other levels is H::digest(0x01 || left || right).
+
+use super::MerkleHasher;
+
+const RFC6962_LEAF_PREFIX: u8 = 0x00;
+const RFC6962_NODE_PREFIX: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TreeShape {
+    #[default]
+    Bitcoin,
+    Rfc6962,
+}
+
+/// Number of hashes in each tree level (from zero level to root) for the given number of blocks
+pub(crate) fn level_sizes(num_block: usize, shape: TreeShape) -> Vec<usize> {
+    match checked_level_sizes(num_block, shape) {
+        Some(sizes) => sizes,
+        None => panic!("Too many blocks {}", num_block),
+    }
+}
+
+/// As `level_sizes`, None if size of zero level overflows. For untrusted number of blocks
+pub(crate) fn checked_level_sizes(num_block: usize, shape: TreeShape) -> Option<Vec<usize>> {
+    let mut sizes: Vec<usize> = Vec::new();
+
+    match shape {
+        TreeShape::Bitcoin => {
+            let mut size = num_block.checked_add(num_block % 2)?;
+            sizes.push(size);
+
+            while size > 1 {
+                size = if size > 2 { size / 2 + (size / 2) % 2 } else { 1 };
+                sizes.push(size);
+            }
+        }
+        TreeShape::Rfc6962 => {
+            let mut size = num_block;
+            sizes.push(size);
+
+            while size > 1 {
+                size = size / 2 + size % 2;
+                sizes.push(size);
+            }
+        }
+    }
+
+    Some(sizes)
+}
+
+/// Hash of input block for zero level
+pub(crate) fn hash_leaf<H: MerkleHasher>(shape: TreeShape, data: &[u8], hashed: &mut [u8]) {
+    match shape {
+        TreeShape::Bitcoin => H::hash_leaf(data, hashed),
+        TreeShape::Rfc6962 => {
+            let mut input: Vec<u8> = Vec::with_capacity(data.len() + 1);
+            input.push(RFC6962_LEAF_PREFIX);
+            input.extend_from_slice(data);
+            H::digest(&input, hashed);
+        }
+    }
+}
+
+/// Hash of concatenated children for other levels.
+/// For Rfc6962 `children` may be a single hash, then it is moved up unchanged
+pub(crate) fn hash_children<H: MerkleHasher>(shape: TreeShape, children: &[u8], hashed: &mut [u8]) {
+    match shape {
+        TreeShape::Bitcoin => H::hash_node(children, hashed),
+        TreeShape::Rfc6962 => {
+            if children.len() == H::OUTPUT_LEN {
+                hashed.copy_from_slice(children);
+            } else {
+                let mut input: Vec<u8> = Vec::with_capacity(children.len() + 1);
+                input.push(RFC6962_NODE_PREFIX);
+                input.extend_from_slice(children);
+                H::digest(&input, hashed);
+            }
+        }
+    }
+}
+
+/// Hash of the node with children `left` and `right`
+pub(crate) fn hash_pair<H: MerkleHasher>(shape: TreeShape, left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut input: Vec<u8> = Vec::with_capacity(left.len() + right.len());
+    input.extend_from_slice(left);
+    input.extend_from_slice(right);
+
+    let mut hashed = vec![0u8; H::OUTPUT_LEN];
+    hash_children::<H>(shape, &input, &mut hashed);
+    hashed
+}
@@ -0,0 +1,246 @@
+//! Sparse Merkle hash tree - authenticated map from 256-bit keys to values
+//!
+//! Tree has 2^256 leaves, one for each key. Bits of key (from the most significant bit of
+//! the first byte) choose the path from root to leaf: 0 - left child, 1 - right child.
+//! Leaf of present key is H::hash_leaf(value), leaf of absent key is OUTPUT_LEN zero bytes.
+//! Nodes are H::hash_node(left || right).
+//!
+//! Subtree without keys has the default hash of its height, which is precomputed.
+//! Only nodes with other hashes are stored, so each key costs 256 hashes in memory.
+//!
+//! Proof keeps only siblings which differ from default hashes, the bitmap marks their heights.
+
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+
+use super::{MerkleHasher, Sha256Hasher, TreeShape};
+use shape::hash_pair;
+
+/// Number of bits of key and height of the tree
+const DEPTH: usize = 256;
+
+/// Proof of membership (value of key) or non-membership (key is absent) in sparse tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
+pub struct SparseProof<H = Sha256Hasher> {
+    /// Bit `height` (from the least significant bit of the first byte) is set if sibling
+    /// of the node of this height on the path is not default
+    pub bitmap: [u8; 32],
+    /// Siblings which are not default, ordered from leaf to root
+    #[cfg_attr(feature = "serde", serde(with = "::serde_impl::hashes"))]
+    pub siblings: Vec<Vec<u8>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hasher: PhantomData<H>,
+}
+
+pub struct SparseMerkleTree<H = Sha256Hasher> {
+    values: BTreeMap<[u8; 32], Vec<u8>>,
+    /// Not default nodes by height and prefix: key with zeroed `height` last bits
+    nodes: HashMap<(usize, [u8; 32]), Vec<u8>>,
+    /// Default hash for each height from leaf to root
+    defaults: Vec<Vec<u8>>,
+    hasher: PhantomData<H>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> SparseMerkleTree {
+        SparseMerkleTree::default()
+    }
+}
+
+impl<H: MerkleHasher> Default for SparseMerkleTree<H> {
+    fn default() -> SparseMerkleTree<H> {
+        SparseMerkleTree {
+            values: BTreeMap::new(),
+            nodes: HashMap::new(),
+            defaults: default_hashes::<H>(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: MerkleHasher> SparseMerkleTree<H> {
+    pub fn get_root(&self) -> &[u8] {
+        self.node(DEPTH, &[0u8; 32])
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<&[u8]> {
+        self.values.get(key).map(|value| value.as_slice())
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Set value of key, return the previous value
+    pub fn insert(&mut self, key: [u8; 32], value: Vec<u8>) -> Option<Vec<u8>> {
+        let mut leaf = vec![0u8; H::OUTPUT_LEN];
+        H::hash_leaf(&value, &mut leaf);
+
+        self.update_path(&key, leaf);
+        self.values.insert(key, value)
+    }
+
+    /// Delete key, return its value
+    pub fn remove(&mut self, key: &[u8; 32]) -> Option<Vec<u8>> {
+        let value = self.values.remove(key);
+
+        if value.is_some() {
+            let leaf = self.defaults[0].clone();
+            self.update_path(key, leaf);
+        }
+
+        value
+    }
+
+    /// Proof for key: membership if tree contains the key, otherwise non-membership
+    pub fn prove(&self, key: &[u8; 32]) -> SparseProof<H> {
+        let mut bitmap = [0u8; 32];
+        let mut siblings: Vec<Vec<u8>> = Vec::new();
+        let mut prefix = *key;
+
+        for height in 0..DEPTH {
+            let sibling = self.node(height, &sibling_prefix(&prefix, height));
+
+            if sibling != self.defaults[height].as_slice() {
+                bitmap[height / 8] |= 1 << (height % 8);
+                siblings.push(sibling.to_vec());
+            }
+
+            clear_bit(&mut prefix, height);
+        }
+
+        SparseProof {
+            bitmap,
+            siblings,
+            hasher: PhantomData,
+        }
+    }
+
+    /// Hash of node of height `height` with key prefix `prefix`
+    fn node(&self, height: usize, prefix: &[u8; 32]) -> &[u8] {
+        match self.nodes.get(&(height, *prefix)) {
+            Some(hash) => hash,
+            None => &self.defaults[height],
+        }
+    }
+
+    /// Set leaf of key and recompute hashes on the path to root
+    fn update_path(&mut self, key: &[u8; 32], leaf: Vec<u8>) {
+        let mut hash = leaf;
+        // Key with zeroed `height` last bits is updated while going up
+        let mut prefix = *key;
+
+        for height in 0..DEPTH {
+            let parent = {
+                let sibling = self.node(height, &sibling_prefix(&prefix, height));
+
+                if hash == self.defaults[height] && sibling == self.defaults[height].as_slice() {
+                    self.defaults[height + 1].clone()
+                } else if is_right(key, height) {
+                    hash_pair::<H>(TreeShape::Bitcoin, sibling, &hash)
+                } else {
+                    hash_pair::<H>(TreeShape::Bitcoin, &hash, sibling)
+                }
+            };
+
+            self.set_node(height, &prefix, hash);
+            clear_bit(&mut prefix, height);
+            hash = parent;
+        }
+
+        self.set_node(DEPTH, &prefix, hash);
+    }
+
+    /// Store node with key prefix `prefix`, default node is not stored
+    fn set_node(&mut self, height: usize, prefix: &[u8; 32], hash: Vec<u8>) {
+        if hash == self.defaults[height] {
+            self.nodes.remove(&(height, *prefix));
+        } else {
+            self.nodes.insert((height, *prefix), hash);
+        }
+    }
+}
+
+/// Check that key has value (Some) or is absent (None) in sparse tree with root `root`
+pub fn verify_sparse<H: MerkleHasher>(
+    root: &[u8],
+    key: &[u8; 32],
+    value: Option<&[u8]>,
+    proof: &SparseProof<H>,
+) -> bool {
+    let mut default = vec![0u8; H::OUTPUT_LEN];
+    let mut hash = default.clone();
+
+    if let Some(value) = value {
+        H::hash_leaf(value, &mut hash);
+    }
+
+    let mut siblings = proof.siblings.iter();
+
+    for height in 0..DEPTH {
+        let sibling = if proof.bitmap[height / 8] & (1 << (height % 8)) != 0 {
+            match siblings.next() {
+                Some(sibling) if sibling.len() == H::OUTPUT_LEN => sibling.clone(),
+                _ => return false,
+            }
+        } else {
+            default.clone()
+        };
+
+        hash = if is_right(key, height) {
+            hash_pair::<H>(TreeShape::Bitcoin, &sibling, &hash)
+        } else {
+            hash_pair::<H>(TreeShape::Bitcoin, &hash, &sibling)
+        };
+
+        default = hash_pair::<H>(TreeShape::Bitcoin, &default, &default);
+    }
+
+    siblings.next().is_none() && hash.as_slice() == root
+}
+
+/// Default hashes of subtrees without keys for each height from leaf to root
+fn default_hashes<H: MerkleHasher>() -> Vec<Vec<u8>> {
+    let mut defaults: Vec<Vec<u8>> = Vec::with_capacity(DEPTH + 1);
+    defaults.push(vec![0u8; H::OUTPUT_LEN]);
+
+    for height in 0..DEPTH {
+        let parent = hash_pair::<H>(TreeShape::Bitcoin, &defaults[height], &defaults[height]);
+        defaults.push(parent);
+    }
+
+    defaults
+}
+
+/// Byte and mask of the bit of key which chooses the child of height `height`
+fn key_bit(height: usize) -> (usize, u8) {
+    let bit = DEPTH - 1 - height;
+    (bit / 8, 0x80 >> (bit % 8))
+}
+
+/// Bit of key which chooses the child of height `height` (0 - left, 1 - right)
+fn is_right(key: &[u8; 32], height: usize) -> bool {
+    let (byte, mask) = key_bit(height);
+    key[byte] & mask != 0
+}
+
+/// Turn prefix of the node of height `height` (key with zeroed `height` last bits)
+/// into prefix of its parent
+fn clear_bit(prefix: &mut [u8; 32], height: usize) {
+    let (byte, mask) = key_bit(height);
+    prefix[byte] &= !mask;
+}
+
+/// Prefix of sibling of the node of height `height` with prefix `prefix`
+fn sibling_prefix(prefix: &[u8; 32], height: usize) -> [u8; 32] {
+    let mut sibling = *prefix;
+    let (byte, mask) = key_bit(height);
+    sibling[byte] ^= mask;
+    sibling
+}
@@ -0,0 +1,256 @@
+//! Changes of Merkle hash tree without full rebuild
+//!
+//! Appended blocks change only the right spine of tree: each level is recomputed from the first
+//! hash which depends on new blocks. The copy of last block (TreeShape::Bitcoin) is dropped
+//! before appending and made again for the new last block.
+//!
+//! Updated block changes only hashes on the path from it to root. If the changed hash has
+//! a copy (it is the last block of level with odd number of blocks) then the copy is updated too.
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+
+use rayon;
+use rayon::prelude::*;
+
+use super::{copy_last_data, install, shared_pool, MerkleError, MerkleHasher, MerkleTree};
+use shape::{hash_children, hash_leaf, level_sizes, TreeShape};
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Append one block to the tree
+    pub fn push(&mut self, block: &[u8]) {
+        let mut hashed = vec![0u8; H::OUTPUT_LEN];
+        hash_leaf::<H>(self.shape, block, &mut hashed);
+
+        self.append_leaf_hashes(&hashed);
+    }
+
+    /// Append blocks to the tree. Root is the same as root of tree built from all blocks at once
+    pub fn extend<B: AsRef<[u8]>>(&mut self, blocks: &[B]) {
+        if blocks.is_empty() {
+            return;
+        }
+
+        let mut hashes = vec![0u8; blocks.len() * H::OUTPUT_LEN];
+
+        for (block, hashed) in blocks.iter().zip(hashes.chunks_mut(H::OUTPUT_LEN)) {
+            hash_leaf::<H>(self.shape, block.as_ref(), hashed);
+        }
+
+        self.append_leaf_hashes(&hashes);
+    }
+
+    /// Append contiguous hashes to zero level and recompute right spine of the tree
+    fn append_leaf_hashes(&mut self, hashes: &[u8]) {
+        let size_hash = H::OUTPUT_LEN;
+        let first = self.num_leaves;
+
+        self.num_leaves += hashes.len() / size_hash;
+        let sizes = level_sizes(self.num_leaves, self.shape);
+
+        {
+            let base = &mut self.tree[0];
+            base.truncate(first * size_hash);
+            base.extend_from_slice(hashes);
+
+            if sizes[0] > self.num_leaves {
+                base.resize(sizes[0] * size_hash, 0u8);
+                copy_last_data(base, sizes[0], size_hash);
+            }
+        }
+
+        while self.tree.len() < sizes.len() {
+            self.tree.push(Vec::new());
+        }
+
+        let mut dirty = first;
+
+        for (level, &size) in sizes.iter().enumerate().skip(1) {
+            dirty /= 2;
+            self.tree[level].resize(size * size_hash, 0u8);
+
+            for index in dirty..size {
+                self.rehash_node(level, index);
+            }
+        }
+    }
+
+    /// Replace block `index` and recompute hashes on the path to root
+    pub fn update(&mut self, index: usize, block: &[u8]) {
+        if let Err(e) = self.try_update(index, block) {
+            panic!("{} in update()", e);
+        }
+    }
+
+    pub fn try_update(&mut self, index: usize, block: &[u8]) -> Result<(), MerkleError> {
+        if index >= self.num_leaves {
+            return Err(MerkleError::IndexOutOfRange { index });
+        }
+
+        let size_hash = H::OUTPUT_LEN;
+        hash_leaf::<H>(
+            self.shape,
+            block,
+            &mut self.tree[0][index * size_hash..(index + 1) * size_hash],
+        );
+        self.update_copy(0, index);
+
+        let mut i = index;
+
+        for level in 1..self.tree.len() {
+            i /= 2;
+            self.rehash_node(level, i);
+            self.update_copy(level, i);
+        }
+
+        Ok(())
+    }
+
+    /// Replace many blocks at once. Each changed hash is recomputed only once and hashes of
+    /// one level are computed in parallel. If index is repeated then the last block is used
+    pub fn update_many<B: AsRef<[u8]>>(&mut self, updates: &[(usize, B)], num_cpus: usize) {
+        if let Err(e) = self.try_update_many(updates, num_cpus) {
+            panic!("{} in update_many()", e);
+        }
+    }
+
+    pub fn try_update_many<B: AsRef<[u8]>>(
+        &mut self,
+        updates: &[(usize, B)],
+        num_cpus: usize,
+    ) -> Result<(), MerkleError> {
+        let pool = shared_pool(num_cpus)?;
+
+        self.try_update_many_in(updates, Some(&pool))
+    }
+
+    pub fn update_many_in<B: AsRef<[u8]>>(
+        &mut self,
+        updates: &[(usize, B)],
+        pool: Option<&rayon::ThreadPool>,
+    ) {
+        if let Err(e) = self.try_update_many_in(updates, pool) {
+            panic!("{} in update_many_in()", e);
+        }
+    }
+
+    pub fn try_update_many_in<B: AsRef<[u8]>>(
+        &mut self,
+        updates: &[(usize, B)],
+        pool: Option<&rayon::ThreadPool>,
+    ) -> Result<(), MerkleError> {
+        if let Some(&(index, _)) = updates.iter().find(|&&(index, _)| index >= self.num_leaves) {
+            return Err(MerkleError::IndexOutOfRange { index });
+        }
+
+        let size_hash = H::OUTPUT_LEN;
+        let shape = self.shape;
+
+        // The last update of each index wins
+        let blocks: BTreeMap<usize, &[u8]> = updates
+            .iter()
+            .map(|&(index, ref block)| (index, block.as_ref()))
+            .collect();
+        let blocks: Vec<(usize, &[u8])> = blocks.into_iter().collect();
+
+        let hashes: Vec<(usize, Vec<u8>)> = install(pool, || {
+            blocks
+                .par_iter()
+                .map(|&(index, block)| {
+                    let mut hashed = vec![0u8; size_hash];
+                    hash_leaf::<H>(shape, block, &mut hashed);
+                    (index, hashed)
+                })
+                .collect()
+        });
+
+        let mut indices: BTreeSet<usize> = BTreeSet::new();
+
+        for (index, hashed) in hashes {
+            self.tree[0][index * size_hash..(index + 1) * size_hash].copy_from_slice(&hashed);
+            self.update_copy(0, index);
+            indices.insert(index / 2);
+        }
+
+        for level in 1..self.tree.len() {
+            let parents: Vec<usize> = indices.iter().cloned().collect();
+
+            let hashes: Vec<(usize, Vec<u8>)> = {
+                let prev_level = &self.tree[level - 1];
+
+                install(pool, || {
+                    parents
+                        .par_iter()
+                        .map(|&index| {
+                            let mut hashed = vec![0u8; size_hash];
+                            hash_from_children::<H>(shape, prev_level, index, &mut hashed);
+                            (index, hashed)
+                        })
+                        .collect()
+                })
+            };
+
+            indices.clear();
+
+            for (index, hashed) in hashes {
+                self.tree[level][index * size_hash..(index + 1) * size_hash]
+                    .copy_from_slice(&hashed);
+                self.update_copy(level, index);
+                indices.insert(index / 2);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recompute hash `index` of level `level` from its children.
+    /// Hash without children is the copy of last block (TreeShape::Bitcoin)
+    fn rehash_node(&mut self, level: usize, index: usize) {
+        let size_hash = H::OUTPUT_LEN;
+        let shape = self.shape;
+
+        let (lower, upper) = self.tree.split_at_mut(level);
+        let prev_level = &lower[level - 1];
+        let current_level = &mut upper[0];
+
+        if 2 * index * size_hash < prev_level.len() {
+            hash_from_children::<H>(
+                shape,
+                prev_level,
+                index,
+                &mut current_level[index * size_hash..(index + 1) * size_hash],
+            );
+        } else {
+            copy_last_data(current_level, index + 1, size_hash);
+        }
+    }
+
+    /// Refresh the copy of hash `index` of level `level` if the hash has it
+    fn update_copy(&mut self, level: usize, index: usize) {
+        let size_hash = H::OUTPUT_LEN;
+        let num_block = self.tree[level].len() / size_hash;
+
+        let has_copy = index + 2 == num_block && if level == 0 {
+            index + 1 == self.num_leaves
+        } else {
+            2 * (index + 1) * size_hash >= self.tree[level - 1].len()
+        };
+
+        if has_copy {
+            copy_last_data(&mut self.tree[level], num_block, size_hash);
+        }
+    }
+}
+
+/// Hash `index` of the level above `prev_level` computed from its children
+fn hash_from_children<H: MerkleHasher>(
+    shape: TreeShape,
+    prev_level: &[u8],
+    index: usize,
+    hashed: &mut [u8],
+) {
+    let start = 2 * index * H::OUTPUT_LEN;
+    let end = cmp::min(start + 2 * H::OUTPUT_LEN, prev_level.len());
+
+    hash_children::<H>(shape, &prev_level[start..end], hashed);
+}
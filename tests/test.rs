@@ -5,13 +5,45 @@ extern crate crypto;
 extern crate merkle_tree;
 extern crate num_cpus;
 extern crate rand;
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde_cbor;
+extern crate serde_json;
 extern crate test;
 
 use crypto::digest::Digest;
-use crypto::sha2::Sha256;
+use crypto::sha2::{Sha256, Sha512};
 use rand::Rng;
 use merkle_tree::MerkleTree;
+use merkle_tree::MerkleTreeBuilder;
 use merkle_tree::gen_data;
+use merkle_tree::to_hex_string;
+use merkle_tree::to_base64;
+use merkle_tree::MerkleError;
+use merkle_tree::{verify_file, verify_file_in, Manifest, VerifyReport};
+use merkle_tree::{diff_dir, read_dir_manifest, write_dir_manifest, DirEntry, DirOptions};
+use merkle_tree::TreeShape;
+use merkle_tree::{verify_inclusion, InclusionProof, Side};
+use merkle_tree::verify_consistency;
+use merkle_tree::{verify_sparse, SparseMerkleTree};
+use merkle_tree::{verify_mmr, Mmr};
+use merkle_tree::verify_many;
+use merkle_tree::verify_range;
+use merkle_tree::{Blake2bHasher, Keccak256Hasher, MerkleHasher, Sha1Hasher, Sha256Hasher,
+                  Sha512Hasher};
+
+/// Call generic `check::<H>(shape)` with each hash function and tree shape
+macro_rules! for_each_shape_and_hasher {
+    ($check:ident) => {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            $check::<Sha256Hasher>(shape);
+            $check::<Sha512Hasher>(shape);
+            $check::<Blake2bHasher>(shape);
+            $check::<Keccak256Hasher>(shape);
+            $check::<Sha1Hasher>(shape);
+        }
+    };
+}
 
 #[cfg(test)]
 mod crypto_test {
@@ -67,19 +99,77 @@ mod merkle_test {
     }
 
     #[test]
-    #[should_panic]
-    fn create_with_one_wrong_length_data_block_1() {
+    fn create_from_borrowed_blocks() {
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        let slices: Vec<&[u8]> = data.iter().map(|block| block.as_slice()).collect();
+        assert_eq!(MerkleTree::new(&slices, 1).get_root(), tree.get_root());
+
+        let strings: Vec<String> = vec!["a".to_string(), "bc".to_string(), String::new()];
+        let bytes: Vec<Vec<u8>> = vec![b"a".to_vec(), b"bc".to_vec(), Vec::new()];
+        assert_eq!(
+            MerkleTree::new(&strings, 1).get_root(),
+            MerkleTree::new(&bytes, 1).get_root()
+        );
+        assert_eq!(
+            MerkleTree::new(&["a", "bc", ""], 1).get_root(),
+            MerkleTree::new(&bytes, 1).get_root()
+        );
+    }
+
+    #[test]
+    fn create_with_empty_data_block() {
         let mut data = gen_data(2, 32);
         data[0].clear();
         let tree = MerkleTree::new(&data, num_cpus::get());
+
+        assert_eq!(
+            to_hex_string(tree.get_hash(0, 0)),
+            "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn create_with_one_wrong_length_data_block_2() {
-        let mut data = gen_data(2, 32);
-        data[0].remove(0);
+    fn create_with_short_and_long_data_blocks() {
+        let data: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![b'a'],
+            vec![b'a'; 31],
+            vec![b'a'; 33],
+        ];
         let tree = MerkleTree::new(&data, num_cpus::get());
+
+        assert_eq!(
+            to_hex_string(tree.get_hash(0, 0)),
+            "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"
+        );
+        assert_eq!(
+            to_hex_string(tree.get_hash(0, 1)),
+            "CA978112CA1BBDCAFAC231B39A23DC4DA786EFF8147C4E72B9807785AFEE48BB"
+        );
+        assert_eq!(
+            to_hex_string(tree.get_hash(0, 2)),
+            "61C60B487D1A921E0BCC9BF853DDA0FB159B30BF57B2E2D2C753B00BE15B5A09"
+        );
+        assert_eq!(
+            to_hex_string(tree.get_hash(0, 3)),
+            "852785C805C77E71A22340A54E9D95933ED49121E7D2BF3C2D358854BC1359EA"
+        );
+        assert_eq!(
+            to_hex_string(tree.get_root()),
+            "F16AE0E36EC26338726B8B02FE5BCD38C45A118AFC1F23E1E5EC83C05C83626E"
+        );
+
+        for index in 0..4 {
+            assert!(verify_inclusion(
+                tree.get_root(),
+                data.len(),
+                TreeShape::Bitcoin,
+                &data[index],
+                &tree.prove(index)
+            ));
+        }
     }
 
     #[test]
@@ -209,3 +299,2869 @@ mod merkle_test {
     }
 
 }
+
+#[cfg(test)]
+mod proof_test {
+
+    use super::*;
+
+    #[test]
+    fn prove_and_verify_each_leaf() {
+        for num_block in 1..18 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::new(&data, num_cpus::get());
+
+            for index in 0..num_block {
+                let proof = tree.prove(index);
+                assert_eq!(proof.path.len(), tree.get_num_level() - 1);
+                assert!(verify_inclusion(
+                    tree.get_root(),
+                    data.len(),
+                    TreeShape::Bitcoin,
+                    &data[index],
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn prove_duplicated_last_block() {
+        let data = gen_data(5, 32);
+        let tree = MerkleTree::new(&data, num_cpus::get());
+        let proof = tree.prove(4);
+
+        assert_eq!(proof.path[0], (Side::Right, tree.get_hash(0, 4).to_vec()));
+        assert!(verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[4],
+            &proof
+        ));
+    }
+
+    #[test]
+    fn verify_with_wrong_data() {
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::new(&data, num_cpus::get());
+        let proof = tree.prove(3);
+
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[2],
+            &proof
+        ));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.index = 2;
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3],
+            &wrong_index
+        ));
+
+        let mut wrong_sibling = proof.clone();
+        wrong_sibling.path[1].1[0] ^= 1;
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3],
+            &wrong_sibling
+        ));
+
+        let mut short_path = proof.clone();
+        short_path.path.pop();
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3],
+            &short_path
+        ));
+    }
+
+    #[test]
+    fn forged_proof_of_smaller_tree() {
+        let data = gen_data(4, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        // Inner node sha256(sha256(h0 || h1)) is also the leaf hash of sha256(h0 || h1)
+        let mut inner = [0u8; 32];
+        let mut sha = Sha256::new();
+        sha.input(tree.get_hash(0, 0));
+        sha.input(tree.get_hash(0, 1));
+        sha.result(&mut inner);
+
+        let mut forged = tree.prove(0);
+        forged.tree_size = 2;
+        forged.path.remove(0);
+        assert_eq!(forged.path, vec![(Side::Right, tree.get_hash(1, 1).to_vec())]);
+
+        // Proof is valid only for the size claimed by the proof itself
+        assert!(verify_inclusion(tree.get_root(), 2, TreeShape::Bitcoin, &inner, &forged));
+        assert!(!verify_inclusion(tree.get_root(), 4, TreeShape::Bitcoin, &inner, &forged));
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            2,
+            TreeShape::Bitcoin,
+            &data[0],
+            &tree.prove(0)
+        ));
+
+        // Number of levels of huge tree overflows
+        forged.tree_size = usize::MAX;
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            usize::MAX,
+            TreeShape::Bitcoin,
+            &inner,
+            &forged
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn prove_with_wrong_index() {
+        let data = gen_data(3, 32);
+        let tree = MerkleTree::new(&data, num_cpus::get());
+        tree.prove(3);
+    }
+}
+
+#[cfg(test)]
+mod hasher_test {
+
+    use super::*;
+
+    fn check_hasher<H: MerkleHasher>(shape: TreeShape) {
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::<H>::with_shape(&data, num_cpus::get(), shape);
+
+        // Only Bitcoin shape copies the last block
+        let num_hash = if shape == TreeShape::Bitcoin { 8 } else { 7 };
+        assert_eq!(tree.get_root().len(), H::OUTPUT_LEN);
+        assert_eq!(tree.get_level(0).len(), num_hash * H::OUTPUT_LEN);
+
+        for index in 0..7 {
+            assert!(verify_inclusion(
+                tree.get_root(),
+                data.len(),
+                shape,
+                &data[index],
+                &tree.prove(index)
+            ));
+        }
+    }
+
+    #[test]
+    fn default_hasher_is_double_sha256() {
+        let data = vec![vec![0u8; 32], vec![1u8; 32]];
+        let tree = MerkleTree::new(&data, 1);
+        let tree_256 = MerkleTree::<Sha256Hasher>::build(&data, 1);
+
+        assert_eq!(
+            to_hex_string(tree.get_root()),
+            "6DE6121A99CABDD4A0EC7CD8E822671A12B1139B594564E529888DCAD983199B"
+        );
+        assert_eq!(tree.get_root(), tree_256.get_root());
+    }
+
+    #[test]
+    fn sha512_root() {
+        let data = gen_data(2, 32);
+        let tree = MerkleTree::<Sha512Hasher>::build(&data, 1);
+
+        let mut sha = Sha512::new();
+        let mut level = vec![0u8; 128];
+        sha.input(&data[0]);
+        sha.result(&mut level[..64]);
+        sha.reset();
+        sha.input(&data[1]);
+        sha.result(&mut level[64..]);
+        sha.reset();
+
+        let mut first = vec![0u8; 64];
+        let mut root = vec![0u8; 64];
+        sha.input(&level);
+        sha.result(&mut first);
+        sha.reset();
+        sha.input(&first);
+        sha.result(&mut root);
+
+        assert_eq!(tree.get_level(0), level.as_slice());
+        assert_eq!(tree.get_root(), root.as_slice());
+    }
+
+    #[test]
+    fn all_hashers() {
+        for_each_shape_and_hasher!(check_hasher);
+    }
+
+    #[test]
+    fn different_hashers_give_different_roots() {
+        let data = gen_data(4, 32);
+        let sha256 = MerkleTree::<Sha256Hasher>::build(&data, 1);
+        let keccak = MerkleTree::<Keccak256Hasher>::build(&data, 1);
+
+        assert_ne!(sha256.get_root(), keccak.get_root());
+    }
+}
+
+#[cfg(test)]
+mod error_test {
+
+    use super::*;
+
+    #[test]
+    fn try_new_with_empty() {
+        let data: Vec<Vec<u8>> = Vec::new();
+
+        match MerkleTree::try_new(&data, num_cpus::get()) {
+            Err(MerkleError::EmptyInput) => {}
+            _ => panic!("EmptyInput is expected"),
+        }
+    }
+
+    #[test]
+    fn try_get_with_wrong_level_or_index() {
+        let data = gen_data(3, 32);
+        let tree = MerkleTree::try_new(&data, num_cpus::get()).unwrap();
+
+        assert!(matches!(
+            tree.try_get_level(3),
+            Err(MerkleError::LevelOutOfRange { level: 3 })
+        ));
+        assert!(matches!(
+            tree.try_get_hash(0, 4),
+            Err(MerkleError::IndexOutOfRange { index: 4 })
+        ));
+        assert!(matches!(
+            tree.try_get_parent(2, 0),
+            Err(MerkleError::LevelOutOfRange { level: 2 })
+        ));
+        assert!(matches!(
+            tree.try_get_children(0, 0),
+            Err(MerkleError::LevelOutOfRange { level: 0 })
+        ));
+        assert!(matches!(
+            tree.try_prove(3),
+            Err(MerkleError::IndexOutOfRange { index: 3 })
+        ));
+
+        assert_eq!(tree.try_get_hash(1, 1).unwrap(), tree.get_hash(1, 1));
+        assert_eq!(tree.try_get_parent(0, 3).unwrap(), tree.get_parent(0, 3));
+        assert_eq!(tree.try_get_children(2, 0).unwrap(), tree.get_children(2, 0));
+    }
+
+    #[test]
+    fn try_get_with_overflowing_level_or_index() {
+        let data = gen_data(3, 32);
+        let tree = MerkleTree::try_new(&data, num_cpus::get()).unwrap();
+
+        assert!(matches!(
+            tree.try_get_parent(usize::MAX, 0),
+            Err(MerkleError::LevelOutOfRange { level: usize::MAX })
+        ));
+        assert!(matches!(
+            tree.try_get_children(1, usize::MAX),
+            Err(MerkleError::IndexOutOfRange { index: usize::MAX })
+        ));
+    }
+
+    #[test]
+    fn error_is_std_error() {
+        let error: Box<dyn std::error::Error> = Box::new(MerkleError::IndexOutOfRange { index: 3 });
+        assert_eq!(error.to_string(), "Invalid index 3!");
+    }
+}
+
+#[cfg(test)]
+mod multiproof_test {
+
+    use super::*;
+
+    fn check_all_pairs<H: MerkleHasher>(shape: TreeShape) {
+        for num_block in 1..12 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::<H>::with_shape(&data, 1, shape);
+
+            for first in 0..num_block {
+                for second in first..num_block {
+                    let proof = tree.prove_many(&[second, first]);
+                    let mut indices = vec![first, second];
+                    indices.dedup();
+                    assert_eq!(proof.indices, indices);
+
+                    let leaves: Vec<&Vec<u8>> = indices.iter().map(|&i| &data[i]).collect();
+                    assert!(verify_many(tree.get_root(), num_block, shape, &leaves, &proof));
+
+                    let wrong: Vec<&Vec<u8>> =
+                        indices.iter().map(|&i| &data[(i + 1) % num_block]).collect();
+                    assert_eq!(
+                        verify_many(tree.get_root(), num_block, shape, &wrong, &proof),
+                        num_block == 1
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        for_each_shape_and_hasher!(check_all_pairs);
+    }
+
+    #[test]
+    fn all_leaves() {
+        let data = gen_data(13, 32);
+        let tree = MerkleTree::<Sha512Hasher>::build(&data, 1);
+        let indices: Vec<usize> = (0..13).collect();
+
+        let proof = tree.prove_many(&indices);
+        assert!(proof.hashes.is_empty());
+        assert!(verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &data, &proof));
+    }
+
+    #[test]
+    fn smaller_than_single_proofs() {
+        let data = gen_data(1000, 32);
+        let tree = MerkleTree::new(&data, num_cpus::get());
+
+        let mut rng = rand::thread_rng();
+        let indices: Vec<usize> = (0..100).map(|_| rng.gen_range(0, 1000)).collect();
+
+        let proof = tree.prove_many(&indices);
+        let leaves: Vec<&Vec<u8>> = proof.indices.iter().map(|&i| &data[i]).collect();
+        assert!(verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &leaves, &proof));
+
+        let single: usize = proof.indices.iter().map(|&i| tree.prove(i).path.len()).sum();
+        assert!(proof.hashes.len() < single);
+
+        let range: Vec<usize> = (100..200).collect();
+        let proof = tree.prove_many(&range);
+        let single: usize = range.iter().map(|&i| tree.prove(i).path.len()).sum();
+        assert!(proof.hashes.len() * 50 < single);
+    }
+
+    #[test]
+    fn tampered_proof() {
+        let data = gen_data(9, 32);
+        let tree = MerkleTree::new(&data, 1);
+        let proof = tree.prove_many(&[1, 4, 8]);
+        let leaves = vec![&data[1], &data[4], &data[8]];
+        assert!(verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &leaves, &proof));
+
+        let mut tampered = proof.clone();
+        tampered.hashes.pop();
+        assert!(!verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &leaves, &tampered));
+
+        let mut tampered = proof.clone();
+        tampered.hashes.push(vec![0u8; 32]);
+        assert!(!verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &leaves, &tampered));
+
+        let mut tampered = proof.clone();
+        tampered.indices = vec![4, 1, 8];
+        assert!(!verify_many(tree.get_root(), data.len(), TreeShape::Bitcoin, &leaves, &tampered));
+
+        assert!(!verify_many(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &leaves[..2],
+            &proof
+        ));
+
+        let mut wrong_size = proof.clone();
+        wrong_size.tree_size = 10;
+        assert!(!verify_many(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &leaves,
+            &wrong_size
+        ));
+        assert!(!verify_many(tree.get_root(), 10, TreeShape::Bitcoin, &leaves, &proof));
+
+        assert!(matches!(tree.try_prove_many(&[]), Err(MerkleError::EmptyInput)));
+        assert!(matches!(
+            tree.try_prove_many(&[0, 9]),
+            Err(MerkleError::IndexOutOfRange { index: 9 })
+        ));
+    }
+
+    #[test]
+    fn forged_proof_of_smaller_tree() {
+        let data = gen_data(4, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        // Inner node sha256(sha256(h0 || h1)) is also the leaf hash of sha256(h0 || h1)
+        let mut inner = [0u8; 32];
+        let mut sha = Sha256::new();
+        sha.input(tree.get_hash(0, 0));
+        sha.input(tree.get_hash(0, 1));
+        sha.result(&mut inner);
+
+        let mut forged = tree.prove_many(&[0]);
+        forged.tree_size = 2;
+        forged.hashes.remove(0);
+
+        assert!(verify_many(tree.get_root(), 2, TreeShape::Bitcoin, &[inner], &forged));
+        assert!(!verify_many(tree.get_root(), 4, TreeShape::Bitcoin, &[inner], &forged));
+
+        // Shape is not taken from the proof either
+        let mut other_shape = tree.prove_many(&[0]);
+        other_shape.shape = TreeShape::Rfc6962;
+        assert!(!verify_many(tree.get_root(), 4, TreeShape::Bitcoin, &data[..1], &other_shape));
+        assert!(!verify_many(tree.get_root(), 4, TreeShape::Rfc6962, &data[..1], &other_shape));
+    }
+}
+
+#[cfg(test)]
+mod range_test {
+
+    use super::*;
+
+    fn check_all_ranges<H: MerkleHasher>(shape: TreeShape) {
+        for num_block in 1..14 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::<H>::with_shape(&data, 1, shape);
+
+            for start in 0..num_block {
+                for end in start + 1..num_block + 1 {
+                    let proof = tree.prove_range(start, end);
+
+                    assert!(proof.left.len() + proof.right.len() <= 2 * tree.get_num_level());
+                    assert!(verify_range(
+                        tree.get_root(),
+                        num_block,
+                        shape,
+                        &data[start..end],
+                        &proof
+                    ));
+
+                    let mut wrong = data[start..end].to_vec();
+                    wrong[0][0] ^= 1;
+                    assert!(!verify_range(tree.get_root(), num_block, shape, &wrong, &proof));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        for_each_shape_and_hasher!(check_all_ranges);
+    }
+
+    #[test]
+    fn odd_tail() {
+        // The last block of 7 is hashed with its copy on zero level
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        let proof = tree.prove_range(5, 7);
+        assert_eq!(proof.left.len(), 2);
+        assert!(proof.right.is_empty());
+        assert!(verify_range(tree.get_root(), data.len(), TreeShape::Bitcoin, &data[5..7], &proof));
+    }
+
+    #[test]
+    fn tampered_proof() {
+        let data = gen_data(10, 32);
+        let tree = MerkleTree::new(&data, 1);
+        let proof = tree.prove_range(3, 6);
+        assert!(verify_range(tree.get_root(), data.len(), TreeShape::Bitcoin, &data[3..6], &proof));
+
+        assert!(!verify_range(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3..5],
+            &proof
+        ));
+        assert!(!verify_range(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[4..7],
+            &proof
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.right.push(vec![0u8; 32]);
+        assert!(!verify_range(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3..6],
+            &tampered
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.left.pop();
+        assert!(!verify_range(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3..6],
+            &tampered
+        ));
+
+        assert!(matches!(tree.try_prove_range(3, 3), Err(MerkleError::EmptyInput)));
+        assert!(matches!(
+            tree.try_prove_range(3, 11),
+            Err(MerkleError::IndexOutOfRange { index: 11 })
+        ));
+    }
+
+    #[test]
+    fn forged_proof_of_smaller_tree() {
+        let data = gen_data(4, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        // Inner node sha256(sha256(h0 || h1)) is also the leaf hash of sha256(h0 || h1)
+        let mut inner = [0u8; 32];
+        let mut sha = Sha256::new();
+        sha.input(tree.get_hash(0, 0));
+        sha.input(tree.get_hash(0, 1));
+        sha.result(&mut inner);
+
+        let mut forged = tree.prove_range(0, 1);
+        forged.tree_size = 2;
+        forged.right.remove(0);
+
+        assert!(verify_range(tree.get_root(), 2, TreeShape::Bitcoin, &[inner], &forged));
+        assert!(!verify_range(tree.get_root(), 4, TreeShape::Bitcoin, &[inner], &forged));
+
+        // Shape is not taken from the proof either
+        let mut other_shape = tree.prove_range(0, 1);
+        other_shape.shape = TreeShape::Rfc6962;
+        assert!(!verify_range(tree.get_root(), 4, TreeShape::Bitcoin, &data[..1], &other_shape));
+        assert!(!verify_range(tree.get_root(), 4, TreeShape::Rfc6962, &data[..1], &other_shape));
+    }
+}
+
+#[cfg(test)]
+mod rfc6962_test {
+
+    use super::*;
+
+    // Test vectors of Certificate Transparency
+    const LEAVES: [&str; 8] = [
+        "",
+        "00",
+        "10",
+        "2021",
+        "3031",
+        "40414243",
+        "5051525354555657",
+        "606162636465666768696a6b6c6d6e6f",
+    ];
+
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn leaves(num_block: usize) -> Vec<Vec<u8>> {
+        LEAVES[..num_block].iter().map(|leaf| from_hex(leaf)).collect()
+    }
+
+    #[test]
+    fn roots_match_test_vectors() {
+        for num_block in 1..9 {
+            let tree = MerkleTree::<Sha256Hasher>::with_shape(
+                &leaves(num_block),
+                num_cpus::get(),
+                TreeShape::Rfc6962,
+            );
+
+            assert_eq!(tree.get_root(), from_hex(ROOTS[num_block - 1]).as_slice());
+        }
+    }
+
+    #[test]
+    fn odd_block_is_moved_up() {
+        let tree =
+            MerkleTree::<Sha256Hasher>::with_shape(&leaves(5), num_cpus::get(), TreeShape::Rfc6962);
+
+        assert_eq!(tree.get_num_level(), 4);
+        assert_eq!(tree.get_level(0).len(), 5 * 32);
+        assert_eq!(tree.get_level(1).len(), 3 * 32);
+        assert_eq!(tree.get_hash(1, 2), tree.get_hash(0, 4));
+        assert_eq!(tree.get_hash(2, 1), tree.get_hash(0, 4));
+        assert!(tree.try_get_children(2, 1).is_err());
+    }
+
+    #[test]
+    fn prove_and_verify_each_leaf() {
+        for num_block in 1..9 {
+            let data = leaves(num_block);
+            let tree =
+                MerkleTree::<Sha256Hasher>::with_shape(&data, num_cpus::get(), TreeShape::Rfc6962);
+
+            for index in 0..num_block {
+                let proof = tree.prove(index);
+                assert!(verify_inclusion(
+                    tree.get_root(),
+                    data.len(),
+                    TreeShape::Rfc6962,
+                    &data[index],
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn audit_path_skips_moved_up_block() {
+        let data = leaves(7);
+        let tree =
+            MerkleTree::<Sha256Hasher>::with_shape(&data, num_cpus::get(), TreeShape::Rfc6962);
+        let proof = tree.prove(6);
+
+        assert_eq!(proof.path.len(), 2);
+        assert_eq!(proof.path[0], (Side::Left, tree.get_hash(1, 2).to_vec()));
+        assert_eq!(proof.path[1], (Side::Left, tree.get_hash(2, 0).to_vec()));
+        assert!(verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Rfc6962,
+            &data[6],
+            &proof
+        ));
+
+        let mut wrong_shape = proof.clone();
+        wrong_shape.shape = TreeShape::Bitcoin;
+        assert!(!verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Rfc6962,
+            &data[6],
+            &wrong_shape
+        ));
+    }
+
+    #[test]
+    fn forged_proof_of_other_shape() {
+        // Root of one block h of Bitcoin shape is sha256(sha256(h || h)), if sha256(h || h)
+        // starts with zero byte it is also the Rfc6962 leaf hash of the rest of sha256(h || h)
+        let (tree, inner) = (0..)
+            .map(|i: u32| {
+                let tree = MerkleTree::<Sha256Hasher>::new(&[i.to_le_bytes()], 1);
+
+                let mut inner = [0u8; 32];
+                let mut sha = Sha256::new();
+                sha.input(tree.get_hash(0, 0));
+                sha.input(tree.get_hash(0, 0));
+                sha.result(&mut inner);
+
+                (tree, inner)
+            })
+            .find(|&(_, inner)| inner[0] == 0)
+            .unwrap();
+
+        let mut forged = tree.prove(0);
+        forged.shape = TreeShape::Rfc6962;
+        forged.path.clear();
+
+        // Proof is valid only for the shape claimed by the proof itself
+        assert!(verify_inclusion(tree.get_root(), 1, TreeShape::Rfc6962, &inner[1..], &forged));
+        assert!(!verify_inclusion(tree.get_root(), 1, TreeShape::Bitcoin, &inner[1..], &forged));
+    }
+
+    #[test]
+    fn duplicated_leaf_changes_root() {
+        // CVE-2012-2459: Bitcoin shape gives the same root for [a, b, c] and [a, b, c, c]
+        let data = gen_data(3, 32);
+        let mut data_dup = data.clone();
+        data_dup.push(data[2].clone());
+
+        let bitcoin = MerkleTree::new(&data, 1);
+        let bitcoin_dup = MerkleTree::new(&data_dup, 1);
+        assert_eq!(bitcoin.get_root(), bitcoin_dup.get_root());
+
+        let rfc = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, TreeShape::Rfc6962);
+        let rfc_dup = MerkleTree::<Sha256Hasher>::with_shape(&data_dup, 1, TreeShape::Rfc6962);
+        assert_ne!(rfc.get_root(), rfc_dup.get_root());
+    }
+}
+
+#[cfg(test)]
+mod consistency_test {
+
+    use super::*;
+
+    fn check_all_sizes<H: MerkleHasher>(shape: TreeShape) {
+        let data = gen_data(20, 32);
+
+        for new_size in 1..21 {
+            let new_tree = MerkleTree::<H>::with_shape(&data[..new_size], 1, shape);
+
+            for old_size in 1..new_size + 1 {
+                let old_tree = MerkleTree::<H>::with_shape(&data[..old_size], 1, shape);
+                let proof = new_tree.prove_consistency(old_size);
+
+                assert!(verify_consistency(
+                    old_tree.get_root(),
+                    old_size,
+                    new_tree.get_root(),
+                    new_size,
+                    shape,
+                    &proof
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        for_each_shape_and_hasher!(check_all_sizes);
+    }
+
+    #[test]
+    fn rfc6962_proof_size() {
+        // Proofs of RFC 6962, section 2.1.3
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, TreeShape::Rfc6962);
+
+        assert_eq!(tree.prove_consistency(3).hashes.len(), 4);
+        assert_eq!(tree.prove_consistency(4).hashes.len(), 1);
+        assert_eq!(tree.prove_consistency(6).hashes.len(), 3);
+        assert_eq!(
+            tree.prove_consistency(4).hashes[0],
+            tree.get_hash(2, 1).to_vec()
+        );
+    }
+
+    #[test]
+    fn verify_with_wrong_data() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let data = gen_data(11, 32);
+            let old_data = data[..6].to_vec();
+            let new_tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+            let old_tree = MerkleTree::<Sha256Hasher>::with_shape(&old_data, 1, shape);
+            let proof = new_tree.prove_consistency(6);
+
+            let old_root = old_tree.get_root();
+            let new_root = new_tree.get_root();
+
+            assert!(verify_consistency(old_root, 6, new_root, 11, shape, &proof));
+            assert!(!verify_consistency(new_root, 6, new_root, 11, shape, &proof));
+            assert!(!verify_consistency(old_root, 6, old_root, 11, shape, &proof));
+            assert!(!verify_consistency(old_root, 5, new_root, 11, shape, &proof));
+
+            let other_shape = match shape {
+                TreeShape::Bitcoin => TreeShape::Rfc6962,
+                TreeShape::Rfc6962 => TreeShape::Bitcoin,
+            };
+            assert!(!verify_consistency(old_root, 6, new_root, 11, other_shape, &proof));
+
+            let mut wrong_hash = proof.clone();
+            wrong_hash.hashes[0][0] ^= 1;
+            assert!(!verify_consistency(old_root, 6, new_root, 11, shape, &wrong_hash));
+
+            let mut short_proof = proof.clone();
+            short_proof.hashes.pop();
+            assert!(!verify_consistency(old_root, 6, new_root, 11, shape, &short_proof));
+
+            let mut other_data = old_data.clone();
+            other_data[5][0] ^= 1;
+            let other_tree = MerkleTree::<Sha256Hasher>::with_shape(&other_data, 1, shape);
+            assert!(!verify_consistency(other_tree.get_root(), 6, new_root, 11, shape, &proof));
+
+            // Bounds of subtrees of huge tree overflow
+            for &new_size in &[usize::MAX - 1, usize::MAX / 2 + 1] {
+                let mut huge = proof.clone();
+                huge.old_size = 1;
+                huge.new_size = new_size;
+                assert!(!verify_consistency(new_root, 1, new_root, new_size, shape, &huge));
+            }
+        }
+    }
+
+    #[test]
+    fn prove_with_wrong_size() {
+        let data = gen_data(4, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        assert!(matches!(
+            tree.try_prove_consistency(0),
+            Err(MerkleError::SizeOutOfRange { size: 0 })
+        ));
+        assert!(matches!(
+            tree.try_prove_consistency(5),
+            Err(MerkleError::SizeOutOfRange { size: 5 })
+        ));
+        assert!(tree.prove_consistency(4).hashes.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod append_test {
+
+    use super::*;
+
+    fn assert_same_tree<H: MerkleHasher>(tree: &MerkleTree<H>, expected: &MerkleTree<H>) {
+        assert_eq!(tree.get_num_leaves(), expected.get_num_leaves());
+        assert_eq!(tree.get_num_level(), expected.get_num_level());
+
+        for level in 0..expected.get_num_level() {
+            assert_eq!(tree.get_level(level), expected.get_level(level));
+        }
+    }
+
+    #[test]
+    fn push_is_equal_to_build() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let data = gen_data(33, 32);
+            let mut tree = MerkleTree::<Sha256Hasher>::with_shape(&data[..1], 1, shape);
+
+            for num_block in 2..34 {
+                tree.push(&data[num_block - 1]);
+
+                let expected =
+                    MerkleTree::<Sha256Hasher>::with_shape(&data[..num_block], 1, shape);
+                assert_same_tree(&tree, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn extend_is_equal_to_build() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let data = gen_data(40, 32);
+
+            for &(first, second) in &[(1, 1), (1, 6), (3, 9), (5, 35), (16, 1), (17, 23)] {
+                let mut tree =
+                    MerkleTree::<Sha256Hasher>::with_shape(&data[..first], 1, shape);
+                tree.extend(&data[first..first + second]);
+
+                let expected = MerkleTree::<Sha256Hasher>::with_shape(
+                    &data[..first + second],
+                    1,
+                    shape,
+                );
+                assert_same_tree(&tree, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn extend_with_empty() {
+        let data = gen_data(5, 32);
+        let mut tree = MerkleTree::new(&data, 1);
+        tree.extend(&Vec::<Vec<u8>>::new());
+
+        assert_same_tree(&tree, &MerkleTree::new(&data, 1));
+    }
+
+    #[test]
+    fn prove_after_push() {
+        let mut data = gen_data(6, 32);
+        let mut tree = MerkleTree::new(&data, num_cpus::get());
+
+        let block = gen_data(1, 32).pop().unwrap();
+        tree.push(&block);
+        data.push(block);
+
+        for index in 0..7 {
+            assert!(verify_inclusion(
+                tree.get_root(),
+                data.len(),
+                TreeShape::Bitcoin,
+                &data[index],
+                &tree.prove(index)
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod update_test {
+
+    use super::*;
+
+    #[test]
+    fn update_each_block() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            for num_block in 1..12 {
+                let mut data = gen_data(num_block, 32);
+                let mut tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+                for index in 0..num_block {
+                    data[index] = gen_data(1, 16).pop().unwrap();
+                    tree.update(index, &data[index]);
+
+                    let expected = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+                    for level in 0..expected.get_num_level() {
+                        assert_eq!(tree.get_level(level), expected.get_level(level));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn update_duplicated_last_block() {
+        let mut data = gen_data(5, 32);
+        let mut tree = MerkleTree::new(&data, 1);
+
+        data[4] = vec![7u8; 40];
+        tree.update(4, &data[4]);
+
+        assert_eq!(tree.get_hash(0, 4), tree.get_hash(0, 5));
+        assert_eq!(tree.get_hash(1, 2), tree.get_hash(1, 3));
+        assert_eq!(tree.get_root(), MerkleTree::new(&data, 1).get_root());
+    }
+
+    #[test]
+    fn update_many_is_equal_to_build() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let mut data = gen_data(37, 32);
+            let mut tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+            let updates: Vec<(usize, Vec<u8>)> = vec![0, 5, 6, 17, 36, 5, 35]
+                .into_iter()
+                .map(|index| (index, gen_data(1, 32).pop().unwrap()))
+                .collect();
+
+            for &(index, ref block) in &updates {
+                data[index] = block.clone();
+            }
+
+            tree.update_many(&updates, num_cpus::get());
+
+            let expected = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+            for level in 0..expected.get_num_level() {
+                assert_eq!(tree.get_level(level), expected.get_level(level));
+            }
+        }
+    }
+
+    #[test]
+    fn update_many_with_borrowed_blocks() {
+        let data = gen_data(5, 32);
+        let mut tree = MerkleTree::new(&gen_data(5, 32), 1);
+
+        let updates: Vec<(usize, &[u8])> =
+            data.iter().map(|block| &block[..]).enumerate().collect();
+        tree.update_many(&updates, 1);
+        assert_eq!(tree.get_root(), MerkleTree::new(&data, 1).get_root());
+
+        tree.update_many(&[(1, "a"), (3, "b")], 1);
+        let mut expected = data.clone();
+        expected[1] = b"a".to_vec();
+        expected[3] = b"b".to_vec();
+        assert_eq!(tree.get_root(), MerkleTree::new(&expected, 1).get_root());
+    }
+
+    #[test]
+    fn update_with_wrong_index() {
+        let data = gen_data(3, 32);
+        let mut tree = MerkleTree::new(&data, 1);
+        let root = tree.get_root().to_vec();
+
+        assert!(matches!(
+            tree.try_update(3, &data[0]),
+            Err(MerkleError::IndexOutOfRange { index: 3 })
+        ));
+        assert!(matches!(
+            tree.try_update_many(&[(0, &b""[..]), (4, &b""[..])], 1),
+            Err(MerkleError::IndexOutOfRange { index: 4 })
+        ));
+        assert_eq!(tree.get_root(), root.as_slice());
+    }
+}
+
+#[cfg(test)]
+mod binary_test {
+
+    use super::*;
+
+    fn write<H: MerkleHasher>(tree: &MerkleTree<H>) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn check_round_trip<H: MerkleHasher>(shape: TreeShape) {
+        for num_block in 1..10 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::<H>::with_shape(&data, 1, shape);
+            let bytes = write(&tree);
+
+            let loaded = MerkleTree::<H>::read_from(&mut bytes.as_slice()).unwrap();
+
+            assert_eq!(loaded.get_num_leaves(), num_block);
+            assert_eq!(loaded.get_shape(), shape);
+            assert_eq!(loaded.get_num_level(), tree.get_num_level());
+
+            for level in 0..tree.get_num_level() {
+                assert_eq!(loaded.get_level(level), tree.get_level(level));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for_each_shape_and_hasher!(check_round_trip);
+    }
+
+    #[test]
+    fn header() {
+        let tree = MerkleTree::new(&gen_data(3, 32), 1);
+        let bytes = write(&tree);
+
+        assert_eq!(&bytes[..4], b"MRKL");
+        assert_eq!(&bytes[4..7], &[1, Sha256Hasher::ID, 0]);
+        assert_eq!(&bytes[7..15], &[3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&bytes[15..19], &[3, 0, 0, 0]);
+        assert_eq!(bytes.len(), 19 + 3 * 8 + (4 + 2 + 1) * 32);
+    }
+
+    #[test]
+    fn read_invalid_header() {
+        let tree = MerkleTree::new(&gen_data(3, 32), 1);
+        let bytes = write(&tree);
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut wrong_magic.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 9;
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut wrong_version.as_slice()),
+            Err(MerkleError::UnsupportedVersion { version: 9 })
+        ));
+
+        assert!(matches!(
+            MerkleTree::<Keccak256Hasher>::read_from(&mut bytes.as_slice()),
+            Err(MerkleError::HasherMismatch { expected: 4, found: 1 })
+        ));
+
+        let mut wrong_num_level = bytes.clone();
+        wrong_num_level[15] = 4;
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut wrong_num_level.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn read_invalid_level() {
+        let tree = MerkleTree::new(&gen_data(3, 32), 1);
+        let bytes = write(&tree);
+
+        // Length of level 1 is 2 hashes, it starts after level 0 with 4 hashes
+        let mut wrong_length = bytes.clone();
+        wrong_length[19 + 8 + 4 * 32] = 3;
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut wrong_length.as_slice()),
+            Err(MerkleError::InvalidLevel { level: 1 })
+        ));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut &truncated[..]),
+            Err(MerkleError::InvalidLevel { level: 2 })
+        ));
+
+        let truncated_header = &bytes[..10];
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut &truncated_header[..]),
+            Err(MerkleError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn proof_round_trip() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            for num_block in 1..10 {
+                let data = gen_data(num_block, 32);
+                let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+                for index in 0..num_block {
+                    let proof = tree.prove(index);
+
+                    let mut bytes: Vec<u8> = Vec::new();
+                    proof.write_to(&mut bytes).unwrap();
+                    assert_eq!(&bytes[..4], b"MRKP");
+                    assert_eq!(bytes.len(), 27 + proof.path.len() * 33);
+
+                    let loaded = InclusionProof::read_from(&mut bytes.as_slice()).unwrap();
+                    assert_eq!(loaded, proof);
+                    assert!(verify_inclusion(
+                        tree.get_root(),
+                        data.len(),
+                        shape,
+                        &data[index],
+                        &loaded
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_invalid_proof() {
+        let tree = MerkleTree::new(&gen_data(5, 32), 1);
+        let mut bytes: Vec<u8> = Vec::new();
+        tree.prove(2).write_to(&mut bytes).unwrap();
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::read_from(&mut bytes.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+
+        assert!(matches!(
+            InclusionProof::<Sha512Hasher>::read_from(&mut bytes.as_slice()),
+            Err(MerkleError::HasherMismatch { expected: 2, found: 1 })
+        ));
+
+        // Index is not less than number of blocks
+        let mut wrong_index = bytes.clone();
+        wrong_index[7] = 5;
+        assert!(matches!(
+            InclusionProof::<Sha256Hasher>::read_from(&mut wrong_index.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+
+        // Number of blocks does not fit into any tree
+        let mut huge_size = bytes.clone();
+        huge_size[15..23].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            InclusionProof::<Sha256Hasher>::read_from(&mut huge_size.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+
+        let mut wrong_side = bytes.clone();
+        wrong_side[27] = 2;
+        assert!(matches!(
+            InclusionProof::<Sha256Hasher>::read_from(&mut wrong_side.as_slice()),
+            Err(MerkleError::InvalidHeader)
+        ));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            InclusionProof::<Sha256Hasher>::read_from(&mut &truncated[..]),
+            Err(MerkleError::Io(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod pool_test {
+
+    use super::*;
+
+    #[test]
+    fn shared_pool() {
+        let pool = rayon::ThreadPool::new(rayon::Configuration::new().num_threads(4)).unwrap();
+
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            // Levels are smaller than the pool
+            for num_block in 1..40 {
+                let data = gen_data(num_block, 32);
+                let expected = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+                let tree = MerkleTree::<Sha256Hasher>::with_shape_in(&data, shape, Some(&pool));
+                assert_eq!(tree.get_num_level(), expected.get_num_level());
+
+                for level in 0..expected.get_num_level() {
+                    assert_eq!(tree.get_level(level), expected.get_level(level));
+                }
+
+                let global = MerkleTree::<Sha256Hasher>::with_shape_in(&data, shape, None);
+                assert_eq!(global.get_root(), expected.get_root());
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        let pool = rayon::ThreadPool::new(rayon::Configuration::new().num_threads(2)).unwrap();
+        let data: Vec<Vec<u8>> = Vec::new();
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::try_with_shape_in(&data, TreeShape::Bitcoin, Some(&pool)),
+            Err(MerkleError::EmptyInput)
+        ));
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::try_with_shape_in(&data, TreeShape::Rfc6962, None),
+            Err(MerkleError::EmptyInput)
+        ));
+    }
+
+    #[test]
+    fn other_constructors_in_pool() {
+        let pool = rayon::ThreadPool::new(rayon::Configuration::new().num_threads(2)).unwrap();
+        let data = gen_data(8, 32);
+        let buf: Vec<u8> = data.concat();
+        let expected = MerkleTree::<Sha256Hasher>::from_contiguous(&buf, 32, 1);
+
+        for pool in &[Some(&pool), None] {
+            let tree = MerkleTree::<Sha256Hasher>::from_contiguous_in(&buf, 32, *pool);
+            assert_eq!(tree.get_root(), expected.get_root());
+
+            let (tree, len) =
+                MerkleTree::<Sha256Hasher>::from_reader_in(&mut buf.as_slice(), 32, *pool).unwrap();
+            assert_eq!(tree.get_root(), expected.get_root());
+            assert_eq!(len, buf.len() as u64);
+
+            let mut builder = MerkleTreeBuilder::new();
+            builder.extend(&data);
+            assert_eq!(builder.build_in(*pool).get_root(), expected.get_root());
+
+            let leaves: Vec<&[u8]> = expected.get_level(0).chunks(32).collect();
+            let tree = MerkleTree::<Sha256Hasher>::from_leaf_hashes_in(&leaves, *pool);
+            assert_eq!(tree.get_root(), expected.get_root());
+
+            let mut updated = MerkleTree::<Sha256Hasher>::new(&gen_data(8, 32), 1);
+            let updates: Vec<(usize, Vec<u8>)> = data.iter().cloned().enumerate().collect();
+            updated.update_many_in(&updates, *pool);
+            assert_eq!(updated.get_root(), expected.get_root());
+
+            let options = DirOptions::default();
+            assert_eq!(
+                MerkleTree::<Sha256Hasher>::from_dir_entries_in(&[], &options, *pool)
+                    .unwrap()
+                    .get_root(),
+                MerkleTree::<Sha256Hasher>::from_dir_entries(&[], &options, 1)
+                    .unwrap()
+                    .get_root()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod builder_test {
+
+    use super::*;
+
+    fn check_builder<H: MerkleHasher>(shape: TreeShape) {
+        for num_block in 1..20 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::<H>::with_shape(&data, 1, shape);
+
+            let mut builder = MerkleTreeBuilder::<H>::with_shape(shape);
+            builder.extend(data.iter());
+            assert_eq!(builder.get_num_leaves(), num_block);
+
+            let built = builder.build(2);
+
+            assert_eq!(built.get_num_leaves(), num_block);
+            assert_eq!(built.get_num_level(), tree.get_num_level());
+
+            for level in 0..tree.get_num_level() {
+                assert_eq!(built.get_level(level), tree.get_level(level));
+            }
+        }
+    }
+
+    #[test]
+    fn same_as_new() {
+        for_each_shape_and_hasher!(check_builder);
+    }
+
+    #[test]
+    fn push_blocks_one_by_one() {
+        let data = gen_data(5, 32);
+        let mut builder = MerkleTreeBuilder::new();
+
+        for block in &data {
+            builder.push(block);
+        }
+
+        let tree = MerkleTree::new(&data, 1);
+        assert_eq!(builder.build(1).get_root(), tree.get_root());
+    }
+
+    #[test]
+    fn extend_from_iterator() {
+        let mut builder = MerkleTreeBuilder::new();
+        builder.extend((0u8..7).map(|i| vec![i; 100]));
+        builder.extend(vec!["abc", ""]);
+
+        let mut data: Vec<Vec<u8>> = (0u8..7).map(|i| vec![i; 100]).collect();
+        data.push(b"abc".to_vec());
+        data.push(Vec::new());
+
+        let tree = MerkleTree::new(&data, 1);
+        assert_eq!(builder.build(1).get_root(), tree.get_root());
+    }
+
+    #[test]
+    fn build_empty() {
+        let builder = MerkleTreeBuilder::new();
+        assert!(matches!(builder.try_build(1), Err(MerkleError::EmptyInput)));
+    }
+}
+
+#[cfg(test)]
+mod diff_test {
+
+    use super::*;
+
+    #[test]
+    fn equal_trees() {
+        let data = gen_data(10, 32);
+        let tree = MerkleTree::new(&data, 1);
+
+        assert!(tree.diff(&MerkleTree::new(&data, 1)).is_empty());
+    }
+
+    #[test]
+    fn changed_blocks() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            for num_block in 1..20 {
+                let data = gen_data(num_block, 32);
+                let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+                let mut changed = data.clone();
+                let mut expected: Vec<usize> = Vec::new();
+
+                for index in (0..num_block).filter(|i| i % 3 == 1 || *i == num_block - 1) {
+                    changed[index][0] ^= 1;
+                    expected.push(index);
+                }
+
+                let other = MerkleTree::<Sha256Hasher>::with_shape(&changed, 1, shape);
+
+                assert_eq!(tree.diff(&other), expected);
+                assert_eq!(other.diff(&tree), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn different_sizes() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let data = gen_data(21, 32);
+            let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, shape);
+
+            for size in 1..21 {
+                let mut prefix = data[..size].to_vec();
+                prefix[0][0] ^= 1;
+                let other = MerkleTree::<Sha256Hasher>::with_shape(&prefix, 1, shape);
+
+                let mut expected: Vec<usize> = vec![0];
+                expected.extend(size..21);
+
+                assert_eq!(tree.diff(&other), expected);
+                assert_eq!(other.diff(&tree), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn different_shapes() {
+        let data = gen_data(5, 32);
+        let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, TreeShape::Bitcoin);
+        let other = MerkleTree::<Sha256Hasher>::with_shape(&data[..3], 1, TreeShape::Rfc6962);
+
+        assert_eq!(tree.diff(&other), vec![0, 1, 2, 3, 4]);
+    }
+}
+
+#[cfg(test)]
+mod leaf_hashes_test {
+
+    use super::*;
+
+    #[test]
+    fn same_as_new() {
+        for num_block in 1..12 {
+            let data = gen_data(num_block, 32);
+            let tree = MerkleTree::new(&data, 1);
+
+            let hashes: Vec<&[u8]> = (0..num_block).map(|i| tree.get_hash(0, i)).collect();
+            let built = MerkleTree::<Sha256Hasher>::from_leaf_hashes(&hashes, 2);
+
+            assert_eq!(built.get_num_leaves(), num_block);
+
+            for level in 0..tree.get_num_level() {
+                assert_eq!(built.get_level(level), tree.get_level(level));
+            }
+        }
+    }
+
+    #[test]
+    fn sha256_digests() {
+        let records: Vec<&[u8]> = vec![b"first", b"second", b"third"];
+
+        let digests: Vec<Vec<u8>> = records
+            .iter()
+            .map(|record| {
+                let mut hasher = Sha256::new();
+                hasher.input(record);
+                let mut digest = vec![0u8; 32];
+                hasher.result(&mut digest);
+                digest
+            })
+            .collect();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_leaf_hashes(&digests, 1);
+        assert_eq!(tree.get_root(), MerkleTree::new(&records, 1).get_root());
+    }
+
+    #[test]
+    fn invalid_hash_length() {
+        let hashes = vec![vec![0u8; 32], vec![0u8; 31], vec![0u8; 32]];
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::try_from_leaf_hashes(&hashes, 1),
+            Err(MerkleError::InvalidHashLength { index: 1, length: 31 })
+        ));
+        assert!(matches!(
+            MerkleTree::<Sha512Hasher>::try_from_leaf_hashes(&hashes[..1], 1),
+            Err(MerkleError::InvalidHashLength { index: 0, length: 32 })
+        ));
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::try_from_leaf_hashes(&Vec::<Vec<u8>>::new(), 1),
+            Err(MerkleError::EmptyInput)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod file_test {
+
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        (0..len).map(|_| rng.gen::<u8>()).collect()
+    }
+
+    fn chunks(data: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+        data.chunks(chunk_size).map(|chunk| chunk.to_vec()).collect()
+    }
+
+    #[test]
+    fn from_contiguous() {
+        for &len in &[1, 100, 128, 1000] {
+            let data = random_bytes(len);
+
+            let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 32, 2);
+            let expected = MerkleTree::new(&chunks(&data, 32), 1);
+
+            assert_eq!(tree.get_num_leaves(), expected.get_num_leaves());
+            assert_eq!(tree.get_root(), expected.get_root());
+        }
+
+        let empty = MerkleTree::<Sha256Hasher>::from_contiguous(&[], 32, 1);
+        assert_eq!(empty.get_root(), MerkleTree::new(&[b""], 1).get_root());
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::try_from_contiguous(&[1u8, 2, 3], 0, 1),
+            Err(MerkleError::InvalidChunkSize { size: 0 })
+        ));
+    }
+
+    #[test]
+    fn from_reader() {
+        for &len in &[1, 63, 64, 65, 1000, 64 * 100 + 7] {
+            let data = random_bytes(len);
+
+            for &num_cpus in &[1, 3] {
+                let (tree, total_len) =
+                    MerkleTree::<Sha256Hasher>::from_reader(&mut data.as_slice(), 64, num_cpus)
+                        .unwrap();
+
+                let expected = MerkleTree::new(&chunks(&data, 64), 1);
+
+                assert_eq!(total_len, len as u64);
+                assert_eq!(tree.get_num_leaves(), expected.get_num_leaves());
+                assert_eq!(tree.get_root(), expected.get_root());
+            }
+        }
+    }
+
+    #[test]
+    fn from_empty_reader() {
+        let (tree, total_len) =
+            MerkleTree::<Sha256Hasher>::from_reader(&mut [0u8; 0].as_ref(), 64, 1).unwrap();
+
+        assert_eq!(total_len, 0);
+        assert_eq!(tree.get_num_leaves(), 1);
+        assert_eq!(tree.get_root(), MerkleTree::new(&[Vec::new()], 1).get_root());
+    }
+
+    #[test]
+    fn huge_chunk_size() {
+        let data = random_bytes(1000);
+
+        // Batch is not allocated for the whole chunk
+        for &chunk_size in &[usize::MAX, usize::MAX / 2 + 1] {
+            let (tree, total_len) =
+                MerkleTree::<Sha256Hasher>::from_reader(&mut data.as_slice(), chunk_size, 2)
+                    .unwrap();
+
+            assert_eq!(total_len, 1000);
+            assert_eq!(tree.get_root(), MerkleTree::new(&[&data], 1).get_root());
+        }
+
+        // Chunks larger than the batch budget are read one by one
+        let data: Vec<u8> = (0..20 << 20).map(|i: usize| (i % 251) as u8).collect();
+        let (tree, _) =
+            MerkleTree::<Sha256Hasher>::from_reader(&mut data.as_slice(), 9 << 20, 2).unwrap();
+        assert_eq!(tree.get_num_leaves(), 3);
+        assert_eq!(tree.get_root(), MerkleTree::new(&chunks(&data, 9 << 20), 1).get_root());
+    }
+
+    #[test]
+    fn zero_chunk_size() {
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::from_reader(&mut [1u8, 2, 3].as_ref(), 0, 1),
+            Err(MerkleError::InvalidChunkSize { size: 0 })
+        ));
+    }
+
+    #[test]
+    fn from_path() {
+        let data = random_bytes(10000);
+        let path = env::temp_dir().join("merkle_tree_file_test_from_path");
+        fs::write(&path, &data).unwrap();
+
+        let (tree, total_len) = MerkleTree::<Sha512Hasher>::from_path(&path, 1024).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(total_len, 10000);
+        assert_eq!(
+            tree.get_root(),
+            MerkleTree::<Sha512Hasher>::build(&chunks(&data, 1024), 1).get_root()
+        );
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::from_path(&path, 1024),
+            Err(MerkleError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn from_mmap() {
+        let path = env::temp_dir().join("merkle_tree_file_test_from_mmap");
+
+        for &len in &[0, 1, 4096, 100000] {
+            let data = random_bytes(len);
+            fs::write(&path, &data).unwrap();
+
+            let (tree, total_len) = MerkleTree::<Sha256Hasher>::from_mmap(&path, 4096, 2).unwrap();
+            let (expected, _) =
+                MerkleTree::<Sha256Hasher>::from_reader(&mut data.as_slice(), 4096, 1).unwrap();
+
+            assert_eq!(total_len, len as u64);
+            assert_eq!(tree.get_num_leaves(), expected.get_num_leaves());
+            assert_eq!(tree.get_root(), expected.get_root());
+        }
+
+        // Number of chunks does not overflow
+        let data = random_bytes(1000);
+        fs::write(&path, &data).unwrap();
+
+        for &chunk_size in &[usize::MAX, usize::MAX / 2 + 1] {
+            let (tree, total_len) =
+                MerkleTree::<Sha256Hasher>::from_mmap_in(&path, chunk_size, None).unwrap();
+
+            assert_eq!(total_len, 1000);
+            assert_eq!(tree.get_root(), MerkleTree::new(&[&data], 1).get_root());
+        }
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::from_mmap(&path, 0, 1),
+            Err(MerkleError::InvalidChunkSize { size: 0 })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod dir_test {
+
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn make_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+
+        fs::create_dir_all(dir.join("b/c")).unwrap();
+        fs::write(dir.join("b/c/d.txt"), b"d").unwrap();
+        fs::write(dir.join("b/a.txt"), vec![7u8; 3000]).unwrap();
+        fs::write(dir.join("e"), b"").unwrap();
+        fs::write(dir.join("a-z"), b"a-z").unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn sorted_files() {
+        let dir = make_dir("merkle_tree_dir_test_sorted_files");
+        let options = DirOptions::default();
+
+        let (tree, entries) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &options, 2).unwrap();
+
+        let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, vec!["a-z", "b/a.txt", "b/c/d.txt", "e"]);
+
+        // Content hash is root of file chunks
+        let (file_tree, _) =
+            MerkleTree::<Sha256Hasher>::from_path(dir.join("b/a.txt"), options.chunk_size).unwrap();
+        assert_eq!(entries[1].hash, file_tree.get_root());
+
+        let hashes: Vec<Vec<u8>> = entries.iter().map(|entry| entry.hash.clone()).collect();
+        assert_eq!(tree.get_root(), MerkleTree::new(&hashes, 1).get_root());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn path_and_mode() {
+        let dir = make_dir("merkle_tree_dir_test_path_and_mode");
+
+        let contents = DirOptions::default();
+        let with_path = DirOptions {
+            include_path: true,
+            ..DirOptions::default()
+        };
+
+        let (tree, _) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &contents, 1).unwrap();
+        let (tree_path, _) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &with_path, 1).unwrap();
+
+        fs::rename(dir.join("e"), dir.join("f")).unwrap();
+
+        let (renamed, _) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &contents, 1).unwrap();
+        let (renamed_path, _) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &with_path, 1).unwrap();
+
+        assert_eq!(tree.get_root(), renamed.get_root());
+        assert_ne!(tree_path.get_root(), renamed_path.get_root());
+        assert_ne!(tree.get_root(), tree_path.get_root());
+
+        let (_, entries) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &contents, 1).unwrap();
+        let mut changed_mode = entries.clone();
+        changed_mode[0].mode ^= 0o100;
+
+        let with_mode = DirOptions {
+            include_mode: true,
+            ..DirOptions::default()
+        };
+
+        assert_eq!(
+            MerkleTree::<Sha256Hasher>::from_dir_entries(&entries, &contents, 1)
+                .unwrap()
+                .get_root(),
+            MerkleTree::<Sha256Hasher>::from_dir_entries(&changed_mode, &contents, 1)
+                .unwrap()
+                .get_root()
+        );
+        assert_ne!(
+            MerkleTree::<Sha256Hasher>::from_dir_entries(&entries, &with_mode, 1)
+                .unwrap()
+                .get_root(),
+            MerkleTree::<Sha256Hasher>::from_dir_entries(&changed_mode, &with_mode, 1)
+                .unwrap()
+                .get_root()
+        );
+
+        // Change of mode only is not a modification without include_mode
+        assert!(diff_dir(&entries, &changed_mode, &contents).is_empty());
+        assert_eq!(
+            diff_dir(&entries, &changed_mode, &with_mode).modified,
+            vec![entries[0].path.clone()]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_changes() {
+        let dir = make_dir("merkle_tree_dir_test_manifest_changes");
+        let options = DirOptions::default();
+
+        let (_, old) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &options, 1).unwrap();
+
+        let mut manifest: Vec<u8> = Vec::new();
+        write_dir_manifest::<Sha256Hasher, _>(&old, &options, &mut manifest).unwrap();
+        assert!(manifest.starts_with(b"merkle-dir-manifest 1\nhasher sha256\nchunk-size 1024\n"));
+
+        let (loaded_options, loaded) =
+            read_dir_manifest::<Sha256Hasher, _>(&mut manifest.as_slice()).unwrap();
+        assert_eq!(loaded_options, options);
+        assert_eq!(loaded, old);
+
+        let with_path = DirOptions {
+            include_path: true,
+            ..options
+        };
+        let mut manifest_path: Vec<u8> = Vec::new();
+        write_dir_manifest::<Sha256Hasher, _>(&old, &with_path, &mut manifest_path).unwrap();
+        let (loaded_options, _) =
+            read_dir_manifest::<Sha256Hasher, _>(&mut manifest_path.as_slice()).unwrap();
+        assert_eq!(loaded_options, with_path);
+
+        fs::write(dir.join("b/c/d.txt"), b"changed").unwrap();
+        fs::remove_file(dir.join("e")).unwrap();
+        fs::write(dir.join("b/new"), b"new").unwrap();
+
+        let (_, new) = MerkleTree::<Sha256Hasher>::from_dir(&dir, &options, 1).unwrap();
+        let changes = diff_dir(&loaded, &new, &options);
+
+        assert_eq!(changes.added, vec!["b/new"]);
+        assert_eq!(changes.removed, vec!["e"]);
+        assert_eq!(changes.modified, vec!["b/c/d.txt"]);
+        assert!(diff_dir(&new, &new, &options).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_dir() {
+        let dir = env::temp_dir().join("merkle_tree_dir_test_empty_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+
+        // Directory without files is one empty block
+        let (tree, entries) =
+            MerkleTree::<Sha256Hasher>::from_dir(&dir, &DirOptions::default(), 1).unwrap();
+
+        assert!(entries.is_empty());
+        assert_eq!(tree.get_num_leaves(), 1);
+        assert_eq!(tree.get_root(), MerkleTree::new(&[Vec::new()], 1).get_root());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_dir() {
+        let dir = env::temp_dir().join("merkle_tree_dir_test_invalid_dir");
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(matches!(
+            MerkleTree::<Sha256Hasher>::from_dir(&dir, &DirOptions::default(), 1),
+            Err(MerkleError::Io(_))
+        ));
+
+        let header = "merkle-dir-manifest 1\nhasher sha256\nchunk-size 1024\n";
+
+        for &(ref manifest, line) in &[
+            ("00 644 a\n".to_string(), 1),
+            (header.replace("sha256", "sha512"), 2),
+            (header.replace("1024", "0"), 3),
+            (format!("{}include-path 2\ninclude-mode 0\n", header), 4),
+            (format!("{}include-path 0\n", header), 5),
+            (format!("{}include-path 0\ninclude-mode 1\n00 644 a\n", header), 6),
+        ] {
+            match read_dir_manifest::<Sha256Hasher, _>(&mut manifest.as_bytes()) {
+                Err(MerkleError::InvalidManifest { line: found }) => assert_eq!(found, line),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_manifest_entries() {
+        let entry = |path: &str, byte: u8| DirEntry {
+            path: path.to_string(),
+            mode: 0o644,
+            hash: vec![byte; 32],
+        };
+        let options = DirOptions::default();
+        let entries = vec![entry("a", 1), entry("b", 2), entry("c", 3)];
+
+        let mut manifest: Vec<u8> = Vec::new();
+        write_dir_manifest::<Sha256Hasher, _>(&entries, &options, &mut manifest).unwrap();
+        let text = String::from_utf8(manifest).unwrap();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_dir_entries(&entries, &options, 1).unwrap();
+        assert!(text.contains(&format!("\nroot {}\n", to_hex_string(tree.get_root()))));
+
+        let (_, loaded) = read_dir_manifest::<Sha256Hasher, _>(&mut text.as_bytes()).unwrap();
+        assert_eq!(loaded, entries);
+
+        let head: String = text.lines().take(6).map(|line| format!("{}\n", line)).collect();
+        let line = |entry: &DirEntry| {
+            format!("{} 644 {}\n", to_hex_string(&entry.hash), entry.path)
+        };
+        let [a, b, c] = [&entries[0], &entries[1], &entries[2]];
+
+        let swapped = format!("{}{}{}{}", head, line(b), line(a), line(c));
+        let repeated = format!("{}{}{}{}", head, line(a), line(a), line(b));
+        let other_root = text.replace(&to_hex_string(tree.get_root()), &to_hex_string(&[0u8; 32]));
+
+        // Out of order or repeated path, and files which do not give the root
+        for &(ref manifest, line) in &[(swapped, 8), (repeated, 8), (other_root, 6)] {
+            match read_dir_manifest::<Sha256Hasher, _>(&mut manifest.as_bytes()) {
+                Err(MerkleError::InvalidManifest { line: found }) => assert_eq!(found, line),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        // Paths which would not be read back
+        for entries in &[
+            vec![entry("a\r", 1)],
+            vec![entry("a\nb", 1)],
+            vec![entry("b", 1), entry("a", 2)],
+            vec![entry("a", 1), entry("a", 2)],
+        ] {
+            assert!(matches!(
+                write_dir_manifest::<Sha256Hasher, _>(entries, &options, &mut Vec::new()),
+                Err(MerkleError::Io(_))
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod manifest_test {
+
+    use super::*;
+    use std::env;
+    use std::fmt::Debug;
+    use std::fs;
+
+    fn check_manifest<H: MerkleHasher + PartialEq + Debug>(shape: TreeShape) {
+        for &len in &[0, 1, 100, 1024, 3000] {
+            for &chunk_size in &[100, 1024] {
+                let data = gen_data(1, len).remove(0);
+                let chunks: Vec<&[u8]> = if data.is_empty() {
+                    vec![&[]]
+                } else {
+                    data.chunks(chunk_size).collect()
+                };
+
+                let tree = MerkleTree::<H>::with_shape(&chunks, 2, shape);
+                let manifest = Manifest::from_tree(&tree, chunk_size, len as u64);
+                assert_eq!(manifest.get_num_chunks(), tree.get_num_leaves());
+                assert_eq!(manifest.root, tree.get_root());
+
+                if shape == TreeShape::Bitcoin {
+                    assert_eq!(
+                        Manifest::<H>::from_reader(&mut data.as_slice(), chunk_size, 2).unwrap(),
+                        manifest
+                    );
+                }
+
+                let mut text: Vec<u8> = Vec::new();
+                manifest.write_to(&mut text).unwrap();
+                let loaded = Manifest::<H>::read_from(&mut text.as_slice()).unwrap();
+                assert_eq!(loaded, manifest);
+
+                for (index, chunk) in chunks.iter().enumerate() {
+                    assert!(loaded.verify_chunk(index, chunk));
+                }
+
+                assert!(!loaded.verify_chunk(chunks.len(), &[]));
+                assert!(!loaded.verify_chunk(0, &[1u8]));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for_each_shape_and_hasher!(check_manifest);
+    }
+
+    #[test]
+    fn text_format() {
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(b"abcde", 2, 1);
+        let manifest = Manifest::from_tree(&tree, 2, 5);
+
+        let mut text: Vec<u8> = Vec::new();
+        manifest.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 10);
+        assert_eq!(
+            &lines[..5],
+            &[
+                "merkle-manifest 1",
+                "hasher sha256",
+                "shape bitcoin",
+                "chunk-size 2",
+                "length 5",
+            ]
+        );
+        assert_eq!(lines[5], format!("root {}", to_hex_string(tree.get_root())));
+        assert_eq!(lines[6], "leaves 3");
+        assert_eq!(lines[9], to_hex_string(&tree.get_level(0)[64..96]));
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(b"abcde", 2, 1);
+
+        assert!(matches!(
+            Manifest::try_from_tree(&tree, 2, 7),
+            Err(MerkleError::SizeOutOfRange { size: 3 })
+        ));
+        assert!(matches!(
+            Manifest::try_from_tree(&tree, 0, 5),
+            Err(MerkleError::InvalidChunkSize { size: 0 })
+        ));
+
+        let mut text: Vec<u8> = Vec::new();
+        Manifest::from_tree(&tree, 2, 5).write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        assert!(matches!(
+            Manifest::<Sha512Hasher>::read_from(&mut text.as_bytes()),
+            Err(MerkleError::InvalidManifest { line: 2 })
+        ));
+
+        let wrong_length = text.replace("length 5", "length 7");
+        assert!(matches!(
+            Manifest::<Sha256Hasher>::read_from(&mut wrong_length.as_bytes()),
+            Err(MerkleError::InvalidManifest { line: 7 })
+        ));
+
+        // Header promising more chunks than could ever be allocated
+        let lines: Vec<&str> = text.lines().collect();
+        let huge = format!(
+            "{}\n{}\n{}\nchunk-size 1\nlength {}\n{}\nleaves {}\n{}\n",
+            lines[0],
+            lines[1],
+            lines[2],
+            u64::MAX,
+            lines[5],
+            u64::MAX,
+            lines[7]
+        );
+        assert!(matches!(
+            Manifest::<Sha256Hasher>::read_from(&mut huge.as_bytes()),
+            Err(MerkleError::InvalidManifest { line: 9 })
+        ));
+
+        // Hash of chunk does not give the root
+        let wrong_leaf = text.replace(lines[8], lines[7]);
+        assert!(matches!(
+            Manifest::<Sha256Hasher>::read_from(&mut wrong_leaf.as_bytes()),
+            Err(MerkleError::InvalidManifest { line: 6 })
+        ));
+
+        let truncated = &text[..text.len() - 10];
+        assert!(matches!(
+            Manifest::<Sha256Hasher>::read_from(&mut truncated.as_bytes()),
+            Err(MerkleError::InvalidManifest { line: 10 })
+        ));
+    }
+
+    #[test]
+    fn from_path() {
+        let path = env::temp_dir().join("merkle_tree_manifest_test_from_path");
+        let data = gen_data(1, 2500).remove(0);
+        fs::write(&path, &data).unwrap();
+
+        let manifest = Manifest::<Sha256Hasher>::from_path(&path, 1000).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(manifest.chunk_size, 1000);
+        assert_eq!(manifest.total_len, 2500);
+        assert_eq!(manifest.get_num_chunks(), 3);
+        assert!(manifest.verify_chunk(2, &data[2000..]));
+
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 1000, 1);
+        assert_eq!(manifest.root, tree.get_root());
+
+        assert!(matches!(
+            Manifest::<Sha256Hasher>::from_path(&path, 1000),
+            Err(MerkleError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn verify_file_chunks() {
+        let path = env::temp_dir().join("merkle_tree_manifest_test_verify_file");
+        let data = gen_data(1, 10000).remove(0);
+
+        let (tree, total_len) =
+            MerkleTree::<Sha256Hasher>::from_reader(&mut data.as_slice(), 100, 1).unwrap();
+        let manifest = Manifest::from_tree(&tree, 100, total_len);
+
+        fs::write(&path, &data).unwrap();
+        assert!(verify_file(&path, &manifest, 4).unwrap().is_intact());
+
+        let mut corrupt = data.clone();
+        corrupt[0] ^= 1;
+        corrupt[4250] ^= 1;
+        corrupt[9999] ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        assert_eq!(verify_file(&path, &manifest, 4).unwrap().corrupt, vec![0, 42, 99]);
+        assert_eq!(verify_file_in(&path, &manifest, None).unwrap().corrupt, vec![0, 42, 99]);
+
+        fs::write(&path, &data[..9550]).unwrap();
+        assert_eq!(
+            verify_file(&path, &manifest, 2).unwrap().corrupt,
+            vec![95, 96, 97, 98, 99]
+        );
+
+        fs::write(&path, b"").unwrap();
+        assert_eq!(
+            verify_file(&path, &manifest, 1).unwrap().corrupt,
+            (0..100).collect::<Vec<usize>>()
+        );
+
+        let empty = MerkleTree::<Sha256Hasher>::from_contiguous(&[], 100, 1);
+        assert!(verify_file(&path, &Manifest::from_tree(&empty, 100, 0), 1)
+            .unwrap()
+            .is_intact());
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(verify_file(&path, &manifest, 1), Err(MerkleError::Io(_))));
+    }
+
+    #[test]
+    fn verify_longer_file() {
+        let path = env::temp_dir().join("merkle_tree_manifest_test_verify_longer_file");
+        let data = gen_data(1, 1050).remove(0);
+        let manifest = Manifest::<Sha256Hasher>::from_reader(&mut data.as_slice(), 100, 1).unwrap();
+
+        // Extra bytes do not make the short last chunk corrupt
+        let mut longer = data.clone();
+        longer.extend_from_slice(&[0u8; 70]);
+        fs::write(&path, &longer).unwrap();
+
+        let report = verify_file(&path, &manifest, 2).unwrap();
+        assert!(report.corrupt.is_empty());
+        assert_eq!(report.extra_bytes, 70);
+        assert!(!report.is_intact());
+
+        longer[1049] ^= 1;
+        fs::write(&path, &longer).unwrap();
+        assert_eq!(
+            verify_file(&path, &manifest, 2).unwrap(),
+            VerifyReport {
+                corrupt: vec![10],
+                extra_bytes: 70,
+            }
+        );
+
+        // Empty file of manifest
+        let empty = Manifest::<Sha256Hasher>::from_reader(&mut [].as_ref(), 100, 1).unwrap();
+        let report = verify_file(&path, &empty, 2).unwrap();
+        assert!(report.corrupt.is_empty());
+        assert_eq!(report.extra_bytes, 1120);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_file_invalid_manifest() {
+        let path = env::temp_dir().join("merkle_tree_manifest_test_invalid_manifest");
+        let data = gen_data(1, 1000).remove(0);
+        fs::write(&path, &data).unwrap();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 100, 1);
+        let manifest = Manifest::from_tree(&tree, 100, 1000);
+
+        let check = |change: fn(&mut Manifest)| {
+            let mut changed = manifest.clone();
+            change(&mut changed);
+
+            match verify_file(&path, &changed, 2) {
+                Err(MerkleError::InvalidManifest { line }) => line,
+                other => panic!("{:?}", other),
+            }
+        };
+
+        assert_eq!(check(|m| m.chunk_size = 0), 4);
+        assert_eq!(check(|m| m.leaves.clear()), 7);
+        assert_eq!(check(|m| m.total_len = 0), 7);
+        assert_eq!(check(|m| m.root.truncate(31)), 6);
+        assert_eq!(check(|m| m.leaves[3].push(0)), 11);
+        assert_eq!(check(|m| m.leaves[3][0] ^= 1), 6);
+
+        // Chunk larger than the file
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, usize::MAX, 1);
+        let manifest = Manifest::from_tree(&tree, usize::MAX, 1000);
+        assert!(verify_file(&path, &manifest, 2).unwrap().is_intact());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod sparse_test {
+
+    use super::*;
+
+    fn key(i: u8) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.input(&[i]);
+        let mut key = [0u8; 32];
+        hasher.result(&mut key);
+        key
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut tree = SparseMerkleTree::new();
+        let empty_root = tree.get_root().to_vec();
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.insert(key(1), b"one".to_vec()), None);
+        assert_eq!(tree.insert(key(2), b"two".to_vec()), None);
+        assert_eq!(tree.insert(key(1), b"uno".to_vec()), Some(b"one".to_vec()));
+
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(&key(1)), Some(&b"uno"[..]));
+        assert_eq!(tree.get(&key(3)), None);
+        assert!(tree.get_root() != empty_root.as_slice());
+
+        assert_eq!(tree.remove(&key(3)), None);
+        assert_eq!(tree.remove(&key(1)), Some(b"uno".to_vec()));
+        assert_eq!(tree.remove(&key(2)), Some(b"two".to_vec()));
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.get_root(), empty_root.as_slice());
+    }
+
+    #[test]
+    fn root_does_not_depend_on_order() {
+        let mut first = SparseMerkleTree::new();
+        let mut second = SparseMerkleTree::new();
+
+        for i in 0..20 {
+            first.insert(key(i), vec![i; 10]);
+            second.insert(key(19 - i), vec![19 - i; 10]);
+        }
+
+        assert_eq!(first.get_root(), second.get_root());
+
+        first.insert(key(20), Vec::new());
+        first.remove(&key(20));
+        assert_eq!(first.get_root(), second.get_root());
+    }
+
+    #[test]
+    fn membership_proof() {
+        let mut tree = SparseMerkleTree::<Sha512Hasher>::default();
+
+        for i in 0..10 {
+            tree.insert(key(i), vec![i; 8]);
+        }
+
+        let root = tree.get_root().to_vec();
+
+        for i in 0..10 {
+            let proof = tree.prove(&key(i));
+
+            // Only a few siblings are not default
+            assert!(proof.siblings.len() < 16);
+            assert!(verify_sparse(&root, &key(i), Some(&[i; 8]), &proof));
+            assert!(!verify_sparse(&root, &key(i), Some(&[i + 1; 8]), &proof));
+            assert!(!verify_sparse(&root, &key(i), None, &proof));
+            assert!(!verify_sparse(&root, &key(i + 1), Some(&[i; 8]), &proof));
+        }
+    }
+
+    #[test]
+    fn non_membership_proof() {
+        let mut tree = SparseMerkleTree::new();
+
+        let proof = tree.prove(&key(0));
+        assert!(proof.siblings.is_empty());
+        assert!(verify_sparse(tree.get_root(), &key(0), None, &proof));
+
+        for i in 0..10 {
+            tree.insert(key(i), vec![i; 8]);
+        }
+
+        let proof = tree.prove(&key(100));
+        assert!(verify_sparse(tree.get_root(), &key(100), None, &proof));
+        assert!(!verify_sparse(tree.get_root(), &key(100), Some(b""), &proof));
+
+        let mut tampered = proof.clone();
+        tampered.siblings.pop();
+        assert!(!verify_sparse(tree.get_root(), &key(100), None, &tampered));
+    }
+}
+
+#[cfg(test)]
+mod mmr_test {
+
+    use super::*;
+
+    #[test]
+    fn perfect_tree_root() {
+        // MMR of 2^k leaves is one perfect tree, it is the same as MerkleTree (except one leaf)
+        for &num_block in &[2, 4, 8, 16] {
+            let data = gen_data(num_block, 32);
+            let mut mmr = Mmr::new();
+            mmr.extend(&data);
+
+            assert_eq!(mmr.get_peaks().len(), 1);
+            assert_eq!(mmr.get_root(), MerkleTree::new(&data, 1).get_root());
+        }
+    }
+
+    #[test]
+    fn peaks_and_bagged_root() {
+        let data = gen_data(11, 32);
+        let mut mmr = Mmr::new();
+        mmr.extend(&data);
+
+        // 11 = 8 + 2 + 1
+        let peaks = mmr.get_peaks();
+        assert_eq!(peaks.len(), 3);
+        assert_eq!(peaks[0], MerkleTree::new(&data[..8], 1).get_root());
+        assert_eq!(peaks[1], MerkleTree::new(&data[8..10], 1).get_root());
+        assert_eq!(peaks[2], MerkleTree::new(&data[10..], 1).get_hash(0, 0));
+
+        let mut right = peaks[1].to_vec();
+        right.extend_from_slice(peaks[2]);
+        let mut bagged = vec![0u8; 32];
+        Sha256Hasher::hash_node(&right, &mut bagged);
+
+        let mut left = peaks[0].to_vec();
+        left.extend_from_slice(&bagged);
+        let mut root = vec![0u8; 32];
+        Sha256Hasher::hash_node(&left, &mut root);
+
+        assert_eq!(mmr.get_root(), root);
+    }
+
+    #[test]
+    fn root_at_previous_size() {
+        let data = gen_data(20, 32);
+        let mut mmr = Mmr::new();
+        let mut roots: Vec<Vec<u8>> = Vec::new();
+
+        for block in &data {
+            mmr.push(block);
+            roots.push(mmr.get_root());
+        }
+
+        for size in 1..21 {
+            assert_eq!(mmr.get_root_at(size), roots[size - 1]);
+        }
+
+        assert!(matches!(mmr.try_get_root_at(0), Err(MerkleError::SizeOutOfRange { size: 0 })));
+        assert!(matches!(Mmr::new().try_get_root(), Err(MerkleError::SizeOutOfRange { size: 0 })));
+    }
+
+    #[test]
+    fn proofs() {
+        let data = gen_data(13, 32);
+        let mut mmr = Mmr::<Sha512Hasher>::default();
+        mmr.extend(&data);
+
+        for size in 1..14 {
+            let root = mmr.get_root_at(size);
+
+            for index in 0..size {
+                let proof = mmr.prove(index, size);
+
+                assert!(verify_mmr(&root, size, &data[index], &proof));
+                assert!(!verify_mmr(&root, size, &data[(index + 1) % 13], &proof));
+            }
+        }
+
+        let proof = mmr.prove(4, 13);
+        let mut tampered = proof.clone();
+        tampered.peaks.pop();
+        assert!(!verify_mmr(&mmr.get_root(), 13, &data[4], &tampered));
+
+        let mut tampered = proof.clone();
+        tampered.size = 12;
+        assert!(!verify_mmr(&mmr.get_root(), 13, &data[4], &tampered));
+        assert!(!verify_mmr(&mmr.get_root(), 12, &data[4], &proof));
+
+        assert!(matches!(mmr.try_prove(13, 13), Err(MerkleError::IndexOutOfRange { index: 13 })));
+        assert!(matches!(mmr.try_prove(0, 14), Err(MerkleError::SizeOutOfRange { size: 14 })));
+    }
+
+    #[test]
+    fn forged_proof_of_smaller_size() {
+        let data = gen_data(2, 32);
+        let mut mmr = Mmr::new();
+        mmr.extend(&data);
+
+        // Single peak sha256(sha256(H(a) || H(b))) is also the leaf hash of sha256(H(a) || H(b))
+        let mut leaves = [0u8; 64];
+        Sha256Hasher::hash_leaf(&data[0], &mut leaves[..32]);
+        Sha256Hasher::hash_leaf(&data[1], &mut leaves[32..]);
+        let mut inner = [0u8; 32];
+        Sha256Hasher::digest(&leaves, &mut inner);
+
+        let mut forged = mmr.prove(0, 2);
+        forged.size = 1;
+        forged.path.clear();
+
+        assert!(verify_mmr(&mmr.get_root(), 1, &inner, &forged));
+        assert!(!verify_mmr(&mmr.get_root(), 2, &inner, &forged));
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_test {
+
+    use super::*;
+    use merkle_tree::{from_hex_string, ConsistencyProof, InclusionProof};
+
+    fn check_tree<H: MerkleHasher>(tree: &MerkleTree<H>, loaded: &MerkleTree<H>) {
+        assert_eq!(loaded.get_num_leaves(), tree.get_num_leaves());
+        assert_eq!(loaded.get_shape(), tree.get_shape());
+        assert_eq!(loaded.get_num_level(), tree.get_num_level());
+
+        for level in 0..tree.get_num_level() {
+            assert_eq!(loaded.get_level(level), tree.get_level(level));
+        }
+    }
+
+    #[test]
+    fn hex_string() {
+        let bytes: Vec<u8> = vec![0x00, 0x0F, 0xA5, 0xFF];
+
+        assert_eq!(from_hex_string(&to_hex_string(&bytes)), Some(bytes.clone()));
+        assert_eq!(from_hex_string("000fa5ff"), Some(bytes));
+        assert_eq!(from_hex_string("0"), None);
+        assert_eq!(from_hex_string("+1"), None);
+        assert_eq!(from_hex_string("ZZ"), None);
+    }
+
+    #[test]
+    fn tree_json() {
+        for &shape in &[TreeShape::Bitcoin, TreeShape::Rfc6962] {
+            let tree = MerkleTree::<Sha256Hasher>::with_shape(&gen_data(7, 32), 1, shape);
+            let json = serde_json::to_string(&tree).unwrap();
+
+            assert!(json.contains(&to_hex_string(tree.get_root())));
+
+            let loaded: MerkleTree = serde_json::from_str(&json).unwrap();
+            check_tree(&tree, &loaded);
+        }
+    }
+
+    #[test]
+    fn tree_cbor() {
+        let tree = MerkleTree::<Sha512Hasher>::build(&gen_data(5, 32), 1);
+        let bytes = serde_cbor::to_vec(&tree).unwrap();
+
+        // Raw bytes are smaller than hex strings
+        assert!(bytes.len() < serde_json::to_vec(&tree).unwrap().len());
+
+        let loaded: MerkleTree<Sha512Hasher> = serde_cbor::from_slice(&bytes).unwrap();
+        check_tree(&tree, &loaded);
+    }
+
+    #[test]
+    fn tree_invalid() {
+        let tree = MerkleTree::new(&gen_data(3, 32), 1);
+        let json = serde_json::to_string(&tree).unwrap();
+
+        assert!(serde_json::from_str::<MerkleTree<Sha512Hasher>>(&json).is_err());
+
+        let root = to_hex_string(tree.get_root());
+        let short = json.replace(&root, &root[2..]);
+        assert!(serde_json::from_str::<MerkleTree>(&short).is_err());
+
+        let wrong_leaves = json.replace("\"num_leaves\":3", "\"num_leaves\":9");
+        assert!(serde_json::from_str::<MerkleTree>(&wrong_leaves).is_err());
+
+        let huge = r#"{"hasher":1,"shape":"Bitcoin","num_leaves":18446744073709551615,"levels":[]}"#;
+        assert!(serde_json::from_str::<MerkleTree>(huge).is_err());
+    }
+
+    #[test]
+    fn inclusion_proof() {
+        let data = gen_data(7, 32);
+        let tree = MerkleTree::new(&data, 1);
+        let proof = tree.prove(3);
+
+        let json = serde_json::to_string(&proof).unwrap();
+        assert!(json.contains(&to_hex_string(&proof.path[0].1)));
+
+        let loaded: InclusionProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, proof);
+        assert!(verify_inclusion(
+            tree.get_root(),
+            data.len(),
+            TreeShape::Bitcoin,
+            &data[3],
+            &loaded
+        ));
+
+        let loaded: InclusionProof = serde_cbor::from_slice(&serde_cbor::to_vec(&proof).unwrap())
+            .unwrap();
+        assert_eq!(loaded, proof);
+    }
+
+    #[test]
+    fn consistency_proof() {
+        let data = gen_data(9, 32);
+        let old_tree = MerkleTree::<Sha256Hasher>::with_shape(&data[..5], 1,
+                                                              TreeShape::Rfc6962);
+        let tree = MerkleTree::<Sha256Hasher>::with_shape(&data, 1, TreeShape::Rfc6962);
+        let proof = tree.prove_consistency(5);
+
+        let json = serde_json::to_string(&proof).unwrap();
+        let loaded: ConsistencyProof = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, proof);
+        assert!(verify_consistency(
+            old_tree.get_root(),
+            5,
+            tree.get_root(),
+            9,
+            TreeShape::Rfc6962,
+            &loaded
+        ));
+
+        let bytes = serde_cbor::to_vec(&proof).unwrap();
+        let loaded: ConsistencyProof = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(loaded, proof);
+    }
+
+    #[test]
+    fn manifest() {
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(b"abcde", 2, 1);
+        let manifest = Manifest::from_tree(&tree, 2, 5);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains(&format!("\"root\":\"{}\"", to_hex_string(tree.get_root()))));
+
+        let loaded: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, manifest);
+        assert!(loaded.verify_chunk(2, b"e"));
+
+        let bytes = serde_cbor::to_vec(&manifest).unwrap();
+        let loaded: Manifest = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(loaded, manifest);
+
+        // Manifest of other hash function with hashes of the same length
+        assert!(json.starts_with("{\"hasher\":1,"));
+        let error = serde_json::from_str::<Manifest<Keccak256Hasher>>(&json).unwrap_err();
+        assert!(error.to_string().contains(&MerkleError::HasherMismatch {
+            expected: Keccak256Hasher::ID,
+            found: Sha256Hasher::ID,
+        }
+        .to_string()));
+    }
+
+    #[test]
+    fn manifest_invalid() {
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(b"abcde", 2, 1);
+        let manifest = Manifest::from_tree(&tree, 2, 5);
+        let leaf = to_hex_string(&manifest.leaves[0]);
+        let forged = to_hex_string(&[0u8; 32]);
+
+        let json = serde_json::to_string(&manifest).unwrap();
+
+        // Leaves do not give the root, do not match the length or chunk size is zero
+        for invalid in &[
+            json.replace(&leaf, &forged),
+            json.replace("\"total_len\":5", "\"total_len\":7"),
+            json.replace("\"chunk_size\":2", "\"chunk_size\":0"),
+            json.replace(&leaf, "00"),
+        ] {
+            assert_ne!(invalid, &json);
+            assert!(serde_json::from_str::<Manifest>(invalid).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod cli_test {
+
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::{Command, Output};
+
+    fn merkle(args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_merkle"))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn stdout(output: &Output) -> String {
+        String::from_utf8(output.stdout.clone()).unwrap()
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn base64_rfc4648() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+
+        for &(input, encoded) in &vectors {
+            assert_eq!(to_base64(input.as_bytes()), encoded);
+        }
+
+        assert_eq!(to_base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn root_formats() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_root_formats");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let data = gen_data(1, 2500).remove(0);
+        let file = dir.join("data");
+        fs::write(&file, &data).unwrap();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 1000, 1);
+        let root = |options: &[&str]| {
+            let mut args = vec!["root", path_str(&file), "--chunk-size", "1000"];
+            args.extend_from_slice(options);
+            let output = merkle(&args);
+            assert_eq!(output.status.code(), Some(0));
+            stdout(&output)
+        };
+
+        assert_eq!(root(&[]), format!("{}\n", to_hex_string(tree.get_root())));
+        assert_eq!(root(&["--jobs", "1"]), root(&["--format", "hex"]));
+        assert_eq!(
+            root(&["--format", "base64"]),
+            format!("{}\n", to_base64(tree.get_root()))
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&root(&["--format", "json"])).unwrap();
+        assert_eq!(json["root"], to_hex_string(tree.get_root()));
+        assert_eq!(json["leaves"], 3);
+        assert_eq!(json["length"], 2500);
+
+        // Default chunk size is 1024 bytes
+        let output = merkle(&["root", path_str(&file)]);
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 1024, 1);
+        assert_eq!(stdout(&output), format!("{}\n", to_hex_string(tree.get_root())));
+
+        assert_eq!(merkle(&["root"]).status.code(), Some(2));
+        assert_eq!(merkle(&["root", path_str(&file), "--format", "xml"]).status.code(), Some(2));
+        assert_eq!(merkle(&["root", path_str(&file), "--jobs", "0"]).status.code(), Some(2));
+        assert_eq!(merkle(&["root", path_str(&dir.join("missing"))]).status.code(), Some(2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tree_formats() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_tree_formats");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let data = gen_data(1, 300).remove(0);
+        let file = dir.join("data");
+        fs::write(&file, &data).unwrap();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_contiguous(&data, 100, 1);
+        let dump = |format: &str| {
+            let args = ["tree", path_str(&file), "--chunk-size", "100", "--format", format];
+            let output = merkle(&args);
+            assert_eq!(output.status.code(), Some(0));
+            stdout(&output)
+        };
+
+        // Levels are printed as Display of MerkleTree does
+        let text = dump("hex");
+        assert!(text.starts_with("Tree: \nLevel 0: \nhash 0: "));
+        assert!(text.contains(&format!("hash 3: {}\n", to_hex_string(tree.get_hash(0, 3)))));
+        assert!(text.contains(&format!("Level 2: \nhash 0: {}\n", to_hex_string(tree.get_root()))));
+
+        let text = dump("base64");
+        assert!(text.contains(&format!("hash 1: {}\n", to_base64(tree.get_hash(1, 1)))));
+
+        let json: serde_json::Value = serde_json::from_str(&dump("json")).unwrap();
+        assert_eq!(json["leaves"], 3);
+        assert_eq!(json["length"], 300);
+
+        let levels = json["levels"].as_array().unwrap();
+        assert_eq!(levels.len(), tree.get_num_level());
+
+        for (level, hashes) in levels.iter().enumerate() {
+            let hashes: Vec<&str> = hashes
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|hash| hash.as_str().unwrap())
+                .collect();
+            let expected: Vec<String> = tree.get_level(level)
+                .chunks(Sha256Hasher::OUTPUT_LEN)
+                .map(to_hex_string)
+                .collect();
+            assert_eq!(hashes, expected);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_prove_and_verify");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let data = gen_data(10, 1000).concat();
+        let file = dir.join("data");
+        let proof = dir.join("proof");
+        let leaf = dir.join("leaf");
+        fs::write(&file, &data).unwrap();
+        fs::write(&leaf, &data[3000..4000]).unwrap();
+
+        let output = merkle(&[
+            "root",
+            path_str(&file),
+            "--chunk-size",
+            "1000",
+            "--format",
+            "json",
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        assert!(stdout(&output).contains("\"leaves\":10,\"length\":10000"));
+
+        let output = merkle(&[
+            "prove",
+            path_str(&file),
+            "--chunk-size",
+            "1000",
+            "--index",
+            "3",
+            "--out",
+            path_str(&proof),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        let root = stdout(&output).trim().to_string();
+
+        let verify = |size: &str, leaf: &Path| {
+            merkle(&[
+                "verify",
+                "--root",
+                &root,
+                "--size",
+                size,
+                "--leaf",
+                path_str(leaf),
+                "--proof",
+                path_str(&proof),
+            ])
+        };
+
+        let output = verify("10", &leaf);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "OK\n");
+
+        // Wrong size or chunk
+        assert_eq!(verify("9", &leaf).status.code(), Some(1));
+        assert_eq!(verify("10", &file).status.code(), Some(1));
+
+        // Invalid arguments
+        assert_eq!(verify("0", &leaf).status.code(), Some(2));
+        assert_eq!(verify("10", &dir.join("missing")).status.code(), Some(2));
+        let leaf_path = path_str(&leaf);
+        let proof_path = path_str(&proof);
+
+        // Number of chunks is required, the proof does not give it
+        let no_size = ["verify", "--root", &root, "--leaf", leaf_path, "--proof", proof_path];
+        let output = merkle(&no_size);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--size N or --manifest <manifest> is required"));
+        assert!(stderr.contains("not taken from the proof"));
+
+        let bad_root = [
+            "verify", "--root", "xyz", "--size", "10", "--leaf", leaf_path, "--proof", proof_path,
+        ];
+        assert_eq!(merkle(&bad_root).status.code(), Some(2));
+        assert_eq!(merkle(&["unknown"]).status.code(), Some(2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_with_manifest() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_verify_with_manifest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let data = gen_data(5, 1000).concat();
+        let file = dir.join("data");
+        let proof = dir.join("proof");
+        let leaf = dir.join("leaf");
+        let manifest = dir.join("manifest");
+        fs::write(&file, &data).unwrap();
+        fs::write(&leaf, &data[4000..]).unwrap();
+
+        let output = merkle(&[
+            "manifest",
+            path_str(&file),
+            "--chunk-size",
+            "1000",
+            "--out",
+            path_str(&manifest),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        let root = stdout(&output).trim().to_string();
+
+        let loaded = Manifest::<Sha256Hasher>::read_from(&mut fs::File::open(&manifest).unwrap())
+            .unwrap();
+        assert_eq!(to_hex_string(&loaded.root), root);
+        assert_eq!(loaded.get_num_chunks(), 5);
+
+        let output = merkle(&[
+            "prove",
+            path_str(&file),
+            "--chunk-size",
+            "1000",
+            "--index",
+            "4",
+            "--out",
+            path_str(&proof),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+
+        let verify = |options: &[&str], leaf: &Path| {
+            let mut args = vec!["verify", "--leaf", path_str(leaf), "--proof", path_str(&proof)];
+            args.extend_from_slice(options);
+            merkle(&args).status.code()
+        };
+
+        let manifest = path_str(&manifest);
+        assert_eq!(verify(&["--root", &root, "--manifest", manifest], &leaf), Some(0));
+        assert_eq!(verify(&["--manifest", manifest], &leaf), Some(0));
+        assert_eq!(verify(&["--manifest", manifest], &file), Some(1));
+
+        // Root must be the root of manifest
+        let other = to_hex_string(&[0u8; 32]);
+        assert_eq!(verify(&["--root", &other, "--manifest", manifest], &leaf), Some(2));
+        assert_eq!(
+            verify(&["--root", &root, "--size", "5", "--manifest", manifest], &leaf),
+            Some(2)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dir_manifest() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_dir_manifest");
+        let _ = fs::remove_dir_all(&dir);
+        let files = dir.join("files");
+        let manifest = dir.join("manifest");
+        fs::create_dir_all(&files).unwrap();
+
+        // Directory without files has the root of one empty block
+        let output = merkle(&["dir", path_str(&files), "--out", path_str(&manifest)]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            stdout(&output).trim(),
+            to_hex_string(MerkleTree::new(&[Vec::new()], 1).get_root())
+        );
+
+        fs::write(files.join("a"), b"a").unwrap();
+        let output = merkle(&["dir", path_str(&files), "--manifest", path_str(&manifest)]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stdout(&output).contains("added: a"));
+
+        let output = merkle(&["dir", path_str(&files), "--out", path_str(&manifest)]);
+        assert_eq!(output.status.code(), Some(0));
+
+        let compare = |options: &[&str]| {
+            let mut args = vec!["dir", path_str(&files), "--manifest", path_str(&manifest)];
+            args.extend_from_slice(options);
+            merkle(&args).status.code()
+        };
+
+        assert_eq!(compare(&[]), Some(0));
+
+        // Manifest written with other options is not compared
+        assert_eq!(compare(&["--chunk-size", "2"]), Some(2));
+        assert_eq!(compare(&["--with-path"]), Some(2));
+        assert_eq!(compare(&["--with-mode"]), Some(2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forged_proof() {
+        let dir = env::temp_dir().join("merkle_tree_cli_test_forged_proof");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("data");
+        let proof = dir.join("proof");
+        let leaf = dir.join("leaf");
+        fs::write(&file, gen_data(4, 32).concat()).unwrap();
+
+        let (tree, _) = MerkleTree::<Sha256Hasher>::from_path(&file, 32).unwrap();
+
+        // Inner node of the tree of 4 chunks is the root of the tree of 2 chunks
+        let mut inner = [0u8; 32];
+        let mut sha = Sha256::new();
+        sha.input(tree.get_hash(0, 0));
+        sha.input(tree.get_hash(0, 1));
+        sha.result(&mut inner);
+        fs::write(&leaf, inner).unwrap();
+
+        let mut forged = tree.prove(0);
+        forged.tree_size = 2;
+        forged.path.remove(0);
+        forged.write_to(&mut fs::File::create(&proof).unwrap()).unwrap();
+
+        let manifest = dir.join("manifest");
+        Manifest::<Sha256Hasher>::from_path(&file, 32)
+            .unwrap()
+            .write_to(&mut fs::File::create(&manifest).unwrap())
+            .unwrap();
+
+        let root = to_hex_string(tree.get_root());
+        let verify = |options: &[&str]| {
+            let mut args = vec!["verify", "--root", &root, "--leaf", path_str(&leaf)];
+            args.extend_from_slice(&["--proof", path_str(&proof)]);
+            args.extend_from_slice(options);
+            merkle(&args).status.code()
+        };
+
+        // Trusted number of chunks rejects the forged proof
+        assert_eq!(verify(&["--size", "4"]), Some(1));
+        assert_eq!(verify(&["--manifest", path_str(&manifest)]), Some(1));
+
+        // Negative example: the size claimed by the forged proof accepts it,
+        // so verify never takes the size from the proof
+        assert_eq!(verify(&["--size", "2"]), Some(0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}